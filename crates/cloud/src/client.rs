@@ -19,12 +19,12 @@ use cloud_openapi::{
         sql_databases_api::{
             api_sql_databases_create_post, api_sql_databases_database_links_delete,
             api_sql_databases_database_links_post, api_sql_databases_database_rename_patch,
-            api_sql_databases_delete, api_sql_databases_execute_post, api_sql_databases_get,
+            api_sql_databases_delete, api_sql_databases_get,
         },
         variable_pairs_api::{
             api_variable_pairs_delete, api_variable_pairs_get, api_variable_pairs_post,
         },
-        Error,
+        Error, ResponseContent,
     },
     models::{
         AppItem, AppItemPage, ChannelRevisionSelectionStrategy, CreateAppCommand,
@@ -40,7 +40,10 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
-use crate::CloudClientInterface;
+use crate::{
+    models::{CloudCapabilities, KeyValueKeyPage, KeyValuePair, SqlQueryResult},
+    CloudClientInterface,
+};
 
 const JSON_MIME_TYPE: &str = "application/json";
 // Requested API version of cloud service
@@ -92,6 +95,28 @@ impl Client {
 
         Self { configuration }
     }
+
+    /// Starts a request to an endpoint that the generated API client does not
+    /// cover, authenticated in the same way as the generated requests.
+    /// When the new OpenAPI specification is released, manually crafting
+    /// these requests should no longer be necessary.
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let mut builder = self
+            .configuration
+            .client
+            .request(method, format!("{}{}", self.configuration.base_path, path));
+        if let Some(user_agent) = &self.configuration.user_agent {
+            builder = builder.header(header::USER_AGENT, user_agent);
+        }
+        if let Some(api_key) = &self.configuration.api_key {
+            let value = match &api_key.prefix {
+                Some(prefix) => format!("{} {}", prefix, api_key.key),
+                None => api_key.key.clone(),
+            };
+            builder = builder.header(header::AUTHORIZATION, value);
+        }
+        builder
+    }
 }
 
 #[async_trait]
@@ -143,6 +168,19 @@ impl CloudClientInterface for Client {
         .map_err(format_response_error)
     }
 
+    async fn get_capabilities(&self) -> Result<Option<CloudCapabilities>> {
+        let response = self
+            .request(reqwest::Method::GET, "/api/capabilities")
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = ensure_success(response).await?;
+        serde_json::from_reader(response.bytes().await?.as_ref())
+            .context("Failed to parse response")
+    }
+
     async fn add_app(&self, name: &str, storage_id: &str) -> Result<Uuid> {
         api_apps_post(
             &self.configuration,
@@ -239,6 +277,27 @@ impl CloudClientInterface for Client {
         .map_err(format_response_error)
     }
 
+    async fn set_active_revision(&self, channel_id: Uuid, revision_id: Uuid) -> anyhow::Result<()> {
+        let command = PatchChannelCommand {
+            channel_id: Some(channel_id),
+            revision_selection_strategy: Some(
+                ChannelRevisionSelectionStrategy::UseSpecifiedRevision,
+            ),
+            active_revision_id: Some(revision_id),
+            ..Default::default()
+        };
+        let response = self
+            .request(
+                reqwest::Method::PATCH,
+                &format!("/api/channels/{}", channel_id),
+            )
+            .body(serde_json::to_string(&command)?)
+            .send()
+            .await?;
+        ensure_success(response).await?;
+        Ok(())
+    }
+
     // Key value API methods
     async fn add_key_value_pair(
         &self,
@@ -262,6 +321,62 @@ impl CloudClientInterface for Client {
         .map_err(format_response_error)
     }
 
+    async fn get_key_value_pair(
+        &self,
+        store_name: String,
+        key: String,
+    ) -> anyhow::Result<Option<String>> {
+        let response = self
+            .request(reqwest::Method::GET, "/api/key-value-pairs")
+            .query(&[("storeName", &store_name), ("key", &key)])
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = ensure_success(response).await?;
+        let pair: KeyValuePair = serde_json::from_reader(response.bytes().await?.as_ref())
+            .context("Failed to parse response")?;
+        Ok(Some(pair.value))
+    }
+
+    async fn list_key_value_keys(
+        &self,
+        store_name: String,
+        prefix: Option<String>,
+        page_size: i32,
+        page_index: Option<i32>,
+    ) -> anyhow::Result<KeyValueKeyPage> {
+        let mut query = vec![
+            ("storeName", store_name),
+            ("pageSize", page_size.to_string()),
+        ];
+        if let Some(prefix) = prefix {
+            query.push(("prefix", prefix));
+        }
+        if let Some(page_index) = page_index {
+            query.push(("pageIndex", page_index.to_string()));
+        }
+        let response = self
+            .request(reqwest::Method::GET, "/api/key-value-pairs/keys")
+            .query(&query)
+            .send()
+            .await?;
+        let response = ensure_success(response).await?;
+        serde_json::from_reader(response.bytes().await?.as_ref())
+            .context("Failed to parse response")
+    }
+
+    async fn delete_key_value_pair(&self, store_name: String, key: String) -> anyhow::Result<()> {
+        let response = self
+            .request(reqwest::Method::DELETE, "/api/key-value-pairs")
+            .query(&[("storeName", &store_name), ("key", &key)])
+            .send()
+            .await?;
+        ensure_success(response).await?;
+        Ok(())
+    }
+
     async fn create_key_value_store(
         &self,
         store_name: &str,
@@ -386,19 +501,28 @@ impl CloudClientInterface for Client {
         .map_err(format_response_error)
     }
 
-    async fn execute_sql(&self, database: String, statement: String) -> anyhow::Result<()> {
-        api_sql_databases_execute_post(
-            &self.configuration,
-            ExecuteSqlStatementCommand {
-                database,
-                statement,
-                default: false,
-            },
-            None,
-        )
-        .await
-        .map_err(format_response_error)?;
-        Ok(())
+    async fn execute_sql(
+        &self,
+        database: String,
+        statement: String,
+    ) -> anyhow::Result<SqlQueryResult> {
+        let command = ExecuteSqlStatementCommand {
+            database,
+            statement,
+            default: false,
+        };
+        let response = self
+            .request(reqwest::Method::POST, "/api/sql-databases/execute")
+            .body(serde_json::to_string(&command)?)
+            .send()
+            .await?;
+        let response = ensure_success(response).await?;
+        let body = response.bytes().await?;
+        // Instances that do not return rows respond with an empty body
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(SqlQueryResult::default());
+        }
+        serde_json::from_slice(&body).context("Failed to parse response")
     }
 
     async fn delete_database(&self, name: String) -> anyhow::Result<()> {
@@ -477,6 +601,23 @@ fn format_response_error<T>(e: Error<T>) -> anyhow::Error {
     }
 }
 
+/// Turns an unsuccessful response to a manually crafted request into an
+/// error, in the same way as for responses to generated requests
+async fn ensure_success(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let content = response.text().await?;
+    Err(format_response_error(Error::ResponseError(
+        ResponseContent::<()> {
+            status,
+            content,
+            entity: None,
+        },
+    )))
+}
+
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 struct PatchChannelCommand {
     #[serde(rename = "channelId", skip_serializing_if = "Option::is_none")]
@@ -8,6 +8,8 @@ use cloud_openapi::models::{
 use std::string::String;
 use uuid::Uuid;
 
+use crate::models::{CloudCapabilities, KeyValueKeyPage, SqlQueryResult};
+
 #[cfg_attr(feature = "mocks", mockall::automock)]
 #[async_trait]
 pub trait CloudClientInterface: Send + Sync {
@@ -17,6 +19,9 @@ pub trait CloudClientInterface: Send + Sync {
 
     async fn refresh_token(&self, token: String, refresh_token: String) -> Result<TokenInfo>;
 
+    /// Returns None if the instance does not advertise its capabilities
+    async fn get_capabilities(&self) -> Result<Option<CloudCapabilities>>;
+
     async fn add_app(&self, name: &str, storage_id: &str) -> Result<Uuid>;
 
     async fn remove_app(&self, id: String) -> Result<()>;
@@ -47,6 +52,8 @@ pub trait CloudClientInterface: Send + Sync {
         previous: &RevisionItemPage,
     ) -> anyhow::Result<RevisionItemPage>;
 
+    async fn set_active_revision(&self, channel_id: Uuid, revision_id: Uuid) -> anyhow::Result<()>;
+
     async fn add_key_value_pair(
         &self,
         app_id: Option<Uuid>,
@@ -55,6 +62,23 @@ pub trait CloudClientInterface: Send + Sync {
         value: String,
     ) -> anyhow::Result<()>;
 
+    /// Returns None if the store has no value for the key
+    async fn get_key_value_pair(
+        &self,
+        store_name: String,
+        key: String,
+    ) -> anyhow::Result<Option<String>>;
+
+    async fn list_key_value_keys(
+        &self,
+        store_name: String,
+        prefix: Option<String>,
+        page_size: i32,
+        page_index: Option<i32>,
+    ) -> anyhow::Result<KeyValueKeyPage>;
+
+    async fn delete_key_value_pair(&self, store_name: String, key: String) -> anyhow::Result<()>;
+
     async fn create_key_value_store(
         &self,
         store_name: &str,
@@ -99,7 +123,11 @@ pub trait CloudClientInterface: Send + Sync {
         resource_label: Option<ResourceLabel>,
     ) -> anyhow::Result<()>;
 
-    async fn execute_sql(&self, database: String, statement: String) -> anyhow::Result<()>;
+    async fn execute_sql(
+        &self,
+        database: String,
+        statement: String,
+    ) -> anyhow::Result<SqlQueryResult>;
 
     async fn delete_database(&self, name: String) -> anyhow::Result<()>;
 
@@ -1,5 +1,6 @@
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use cloud_openapi::models::RevisionItem;
 use uuid::Uuid;
 
 use crate::CloudClientInterface;
@@ -8,6 +9,12 @@ use crate::CloudClientInterface;
 pub trait CloudClientExt {
     async fn get_app_id(&self, app_name: &str) -> Result<Option<Uuid>>;
     async fn get_revision_id(&self, app_id: Uuid, version: &str) -> Result<Uuid>;
+    async fn list_app_revisions(&self, app_id: Uuid) -> Result<Vec<RevisionItem>>;
+    async fn list_all_key_value_keys(
+        &self,
+        store_name: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<String>>;
 }
 
 #[async_trait]
@@ -46,4 +53,56 @@ impl<T: CloudClientInterface> CloudClientExt for T {
             app_id
         ))
     }
+
+    async fn list_app_revisions(&self, app_id: Uuid) -> Result<Vec<RevisionItem>> {
+        let mut revisions = self.list_revisions().await?;
+        let mut app_revisions = vec![];
+
+        loop {
+            app_revisions.extend(
+                revisions
+                    .items
+                    .iter()
+                    .filter(|&x| x.app_id == app_id)
+                    .cloned(),
+            );
+
+            if revisions.is_last_page {
+                break;
+            }
+
+            revisions = self.list_revisions_next(&revisions).await?;
+        }
+
+        Ok(app_revisions)
+    }
+
+    async fn list_all_key_value_keys(
+        &self,
+        store_name: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        let mut page_index = 0;
+
+        loop {
+            let page = self
+                .list_key_value_keys(
+                    store_name.to_owned(),
+                    prefix.map(str::to_owned),
+                    crate::DEFAULT_APPLIST_PAGE_SIZE,
+                    Some(page_index),
+                )
+                .await?;
+            keys.extend(page.items);
+
+            if page.is_last_page {
+                break;
+            }
+
+            page_index += 1;
+        }
+
+        Ok(keys)
+    }
 }
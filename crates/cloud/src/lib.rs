@@ -1,6 +1,7 @@
 pub mod client;
 mod client_interface;
 mod cloud_client_extensions;
+pub mod models;
 
 pub use client_interface::CloudClientInterface;
 #[cfg(feature = "mocks")]
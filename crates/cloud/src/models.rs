@@ -0,0 +1,68 @@
+//! Types for cloud API endpoints that are not yet described by the OpenAPI
+//! specification
+use serde::{Deserialize, Serialize};
+
+/// The trigger types and host features that a cloud instance can run
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudCapabilities {
+    pub trigger_types: Vec<String>,
+    #[serde(default)]
+    pub host_features: Vec<String>,
+}
+
+impl Default for CloudCapabilities {
+    /// The capabilities of instances that do not advertise their own: HTTP
+    /// triggers only, with no additional host features
+    fn default() -> Self {
+        Self {
+            trigger_types: vec!["http".to_owned()],
+            host_features: vec![],
+        }
+    }
+}
+
+impl CloudCapabilities {
+    pub fn supports_trigger_type(&self, trigger_type: &str) -> bool {
+        self.trigger_types.iter().any(|t| t == trigger_type)
+    }
+}
+
+/// A key value pair in a key value store
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyValuePair {
+    pub key: String,
+    pub value: String,
+}
+
+/// A page of the keys in a key value store
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyValueKeyPage {
+    pub items: Vec<String>,
+    pub total_items: i32,
+    pub page_index: i32,
+    pub page_size: i32,
+    pub is_last_page: bool,
+}
+
+/// The result of executing SQL statements. Statements that return no rows,
+/// such as `CREATE TABLE`, have an empty result.
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SqlQueryResult {
+    #[serde(default)]
+    pub columns: Vec<SqlColumn>,
+    #[serde(default)]
+    pub rows: Vec<Vec<serde_json::Value>>,
+}
+
+/// A column of a SQL query result
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SqlColumn {
+    pub name: String,
+    /// The declared type of the column, if it has one
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub data_type: Option<String>,
+}
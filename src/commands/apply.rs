@@ -0,0 +1,669 @@
+//! Declarative management of an app's cloud environment. A cloud manifest
+//! describes the variables, key value stores, SQLite databases and label
+//! links an app should have; `spin cloud apply` works out what differs in
+//! Fermyon Cloud and converges it.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use cloud::CloudClientInterface;
+use cloud_openapi::models::ResourceLabel;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::commands::links_output::{ResourceLinks, ResourceType};
+use crate::commands::variables::get_variables;
+use crate::commands::{client_and_app_id, CommonArgs};
+
+const DEFAULT_CLOUD_MANIFEST_FILE: &str = "cloud.toml";
+
+/// Converge an app's cloud resources to match a cloud manifest
+#[derive(Parser, Debug)]
+pub struct ApplyCommand {
+    /// Path to the cloud manifest. Files with a `.yaml` or `.yml` extension
+    /// are read as YAML, anything else as TOML.
+    #[clap(short = 'f', long = "file", default_value = DEFAULT_CLOUD_MANIFEST_FILE)]
+    file: PathBuf,
+
+    /// Print the changes that would be made without making them
+    #[clap(long = "dry-run", takes_value = false)]
+    dry_run: bool,
+
+    /// Skips prompt to confirm the changes
+    #[clap(short = 'y', long = "yes", takes_value = false)]
+    yes: bool,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+/// The desired cloud state of an app
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct CloudManifest {
+    /// Name of the app. The app must already have been deployed.
+    app: String,
+    /// Custom domain the app is expected to be served from
+    #[serde(default)]
+    domain: Option<String>,
+    /// Variables to set on the app
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+    /// Databases that must exist, whether or not they are linked to the app
+    #[serde(default)]
+    sqlite_databases: BTreeSet<String>,
+    /// Key value stores that must exist, whether or not they are linked to the app
+    #[serde(default)]
+    key_value_stores: BTreeSet<String>,
+    #[serde(default)]
+    links: ManifestLinks,
+}
+
+/// Label to resource name mappings, by resource type
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct ManifestLinks {
+    #[serde(default)]
+    sqlite: BTreeMap<String, String>,
+    #[serde(default)]
+    kv: BTreeMap<String, String>,
+}
+
+impl CloudManifest {
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read cloud manifest '{}'", path.display()))?;
+        let manifest = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&contents).map_err(anyhow::Error::from),
+            _ => toml::from_str(&contents).map_err(anyhow::Error::from),
+        };
+        manifest.with_context(|| format!("Could not parse cloud manifest '{}'", path.display()))
+    }
+
+    fn links(&self, resource_type: ResourceType) -> &BTreeMap<String, String> {
+        match resource_type {
+            ResourceType::Database => &self.links.sqlite,
+            ResourceType::KeyValueStore => &self.links.kv,
+        }
+    }
+
+    /// The resources of a type that must exist: those declared explicitly
+    /// and those that labels are linked to
+    fn resources(&self, resource_type: ResourceType) -> BTreeSet<&str> {
+        let declared = match resource_type {
+            ResourceType::Database => &self.sqlite_databases,
+            ResourceType::KeyValueStore => &self.key_value_stores,
+        };
+        declared
+            .iter()
+            .chain(self.links(resource_type).values())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// A single change needed to bring the cloud in line with the manifest
+#[derive(Debug, PartialEq)]
+enum Change {
+    CreateResource {
+        resource_type: ResourceType,
+        name: String,
+    },
+    Link {
+        resource_type: ResourceType,
+        label: String,
+        resource: String,
+    },
+    Relink {
+        resource_type: ResourceType,
+        existing: ResourceLabel,
+        from: String,
+        to: String,
+    },
+    SetVariable {
+        name: String,
+        value: String,
+        exists: bool,
+    },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::CreateResource {
+                resource_type,
+                name,
+            } => write!(f, r#"+ create {resource_type} "{name}""#),
+            Change::Link {
+                resource_type,
+                label,
+                resource,
+            } => write!(
+                f,
+                r#"+ link {resource_type} "{resource}" with the label "{label}""#
+            ),
+            Change::Relink {
+                resource_type,
+                existing,
+                from,
+                to,
+            } => write!(
+                f,
+                r#"~ relink the label "{}" from {resource_type} "{from}" to "{to}""#,
+                existing.label
+            ),
+            // Values are deliberately not printed as they may be secrets
+            Change::SetVariable {
+                name, exists: true, ..
+            } => write!(f, r#"~ update variable "{name}""#),
+            Change::SetVariable {
+                name,
+                exists: false,
+                ..
+            } => write!(f, r#"+ add variable "{name}""#),
+        }
+    }
+}
+
+/// The changes needed to converge an app, plus anything that differs but
+/// cannot be changed from here
+#[derive(Debug, Default)]
+struct Plan {
+    changes: Vec<Change>,
+    warnings: Vec<String>,
+}
+
+impl Plan {
+    async fn fetch(
+        client: &impl CloudClientInterface,
+        manifest: &CloudManifest,
+        app_id: Uuid,
+    ) -> Result<Self> {
+        let databases = client
+            .get_databases(None)
+            .await
+            .context("Problem fetching databases")?
+            .into_iter()
+            .map(|d| ResourceLinks::new(d.name, d.links))
+            .collect::<Vec<_>>();
+        let stores = client
+            .get_key_value_stores(None)
+            .await
+            .context("Problem fetching key value stores")?
+            .into_iter()
+            .map(|s| ResourceLinks::new(s.name, s.links))
+            .collect::<Vec<_>>();
+        let variables = get_variables(client, app_id).await?;
+        let variables = variables
+            .iter()
+            .map(|v| (v.key.as_str(), v.value.as_deref()))
+            .collect::<HashMap<_, _>>();
+        let app = client
+            .get_app(app_id.to_string())
+            .await
+            .context("Problem getting app by id")?;
+        let domain = app.domain.as_ref().map(|d| d.name.as_str());
+
+        Ok(Self::compute(
+            manifest, app_id, &databases, &stores, &variables, domain,
+        ))
+    }
+
+    fn compute(
+        manifest: &CloudManifest,
+        app_id: Uuid,
+        databases: &[ResourceLinks],
+        stores: &[ResourceLinks],
+        variables: &HashMap<&str, Option<&str>>,
+        domain: Option<&str>,
+    ) -> Self {
+        let mut plan = Self::default();
+        plan.add_resource_changes(manifest, app_id, ResourceType::Database, databases);
+        plan.add_resource_changes(manifest, app_id, ResourceType::KeyValueStore, stores);
+
+        // A variable whose value the cloud does not return is set regardless,
+        // as there is no telling whether it differs
+        for (name, value) in &manifest.variables {
+            let current = variables.get(name.as_str());
+            if current == Some(&Some(value.as_str())) {
+                continue;
+            }
+            plan.changes.push(Change::SetVariable {
+                name: name.clone(),
+                value: value.clone(),
+                exists: current.is_some(),
+            });
+        }
+
+        if let Some(desired) = &manifest.domain {
+            if domain != Some(desired.as_str()) {
+                plan.warnings.push(format!(
+                    r#"App is served from "{}" but the manifest expects "{desired}". Custom domains must be changed in the Fermyon Cloud dashboard."#,
+                    domain.unwrap_or("no custom domain")
+                ));
+            }
+        }
+        plan
+    }
+
+    fn add_resource_changes(
+        &mut self,
+        manifest: &CloudManifest,
+        app_id: Uuid,
+        resource_type: ResourceType,
+        existing: &[ResourceLinks],
+    ) {
+        for name in manifest.resources(resource_type) {
+            if !existing.iter().any(|r| r.name == name) {
+                self.changes.push(Change::CreateResource {
+                    resource_type,
+                    name: name.to_owned(),
+                });
+            }
+        }
+
+        for (label, resource) in manifest.links(resource_type) {
+            let current = existing.iter().find_map(|r| {
+                r.links
+                    .iter()
+                    .find(|l| l.app_id == app_id && &l.label == label)
+                    .map(|l| (r.name.as_str(), l))
+            });
+            match current {
+                Some((name, _)) if name == resource => {}
+                Some((name, link)) => self.changes.push(Change::Relink {
+                    resource_type,
+                    existing: link.clone(),
+                    from: name.to_owned(),
+                    to: resource.clone(),
+                }),
+                None => self.changes.push(Change::Link {
+                    resource_type,
+                    label: label.clone(),
+                    resource: resource.clone(),
+                }),
+            }
+        }
+    }
+
+    fn print(&self, app: &str) {
+        if !self.changes.is_empty() {
+            println!(r#"Changes to apply to app "{app}":"#);
+            for change in &self.changes {
+                println!("  {change}");
+            }
+        }
+        for warning in &self.warnings {
+            eprintln!("Warning: {warning}");
+        }
+    }
+
+    async fn apply(
+        self,
+        client: &impl CloudClientInterface,
+        app: &str,
+        app_id: Uuid,
+    ) -> Result<()> {
+        for change in self.changes {
+            match change {
+                Change::CreateResource {
+                    resource_type: ResourceType::Database,
+                    name,
+                } => client
+                    .create_database(name.clone(), None)
+                    .await
+                    .with_context(|| format!("Problem creating database {name}"))?,
+                Change::CreateResource {
+                    resource_type: ResourceType::KeyValueStore,
+                    name,
+                } => client
+                    .create_key_value_store(&name, None)
+                    .await
+                    .with_context(|| format!("Problem creating key value store {name}"))?,
+                Change::Link {
+                    resource_type,
+                    label,
+                    resource,
+                } => {
+                    let resource_label = ResourceLabel {
+                        app_id,
+                        label,
+                        app_name: Some(app.to_owned()),
+                    };
+                    create_link(client, resource_type, &resource, resource_label)
+                        .await
+                        .with_context(|| {
+                            format!(r#"Could not link {resource_type} "{resource}" to app "{app}""#)
+                        })?;
+                }
+                Change::Relink {
+                    resource_type,
+                    existing,
+                    from,
+                    to,
+                } => {
+                    let resource_label = ResourceLabel {
+                        app_id,
+                        label: existing.label.clone(),
+                        app_name: Some(app.to_owned()),
+                    };
+                    // A label can only be linked to one resource at a time, so
+                    // the old link is restored if the new one cannot be made
+                    let removed = match resource_type {
+                        ResourceType::Database => {
+                            client.remove_database_link(&from, existing.clone()).await
+                        }
+                        ResourceType::KeyValueStore => {
+                            client
+                                .remove_key_value_store_link(&from, existing.clone())
+                                .await
+                        }
+                    };
+                    removed.with_context(|| {
+                        format!(r#"Could not unlink {resource_type} "{from}" from app "{app}""#)
+                    })?;
+                    if let Err(e) = create_link(client, resource_type, &to, resource_label).await {
+                        let error = e.context(format!(
+                            r#"Could not link {resource_type} "{to}" to app "{app}""#
+                        ));
+                        return match create_link(client, resource_type, &from, existing).await {
+                            Ok(()) => Err(error),
+                            Err(restore) => Err(error.context(format!(
+                                r#"The label is no longer linked: restoring the link to {resource_type} "{from}" failed: {restore:#}"#
+                            ))),
+                        };
+                    }
+                }
+                Change::SetVariable { name, value, .. } => client
+                    .add_variable_pair(app_id, name.clone(), value)
+                    .await
+                    .with_context(|| format!("Problem setting variable {name}"))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn create_link(
+    client: &impl CloudClientInterface,
+    resource_type: ResourceType,
+    resource: &str,
+    resource_label: ResourceLabel,
+) -> Result<()> {
+    match resource_type {
+        ResourceType::Database => client.create_database_link(resource, resource_label).await,
+        ResourceType::KeyValueStore => {
+            client
+                .create_key_value_store_link(resource, resource_label)
+                .await
+        }
+    }
+}
+
+impl ApplyCommand {
+    pub async fn run(self) -> Result<()> {
+        let manifest = CloudManifest::from_file(&self.file)?;
+        let (client, app_id) =
+            client_and_app_id(self.common.deployment_env_id.as_deref(), &manifest.app)
+                .await
+                .context("The app must be deployed with `spin cloud deploy` before its environment can be applied")?;
+
+        let plan = Plan::fetch(&client, &manifest, app_id).await?;
+        plan.print(&manifest.app);
+        if plan.changes.is_empty() {
+            println!(r#"App "{}" is up to date"#, manifest.app);
+            return Ok(());
+        }
+        if self.dry_run {
+            return Ok(());
+        }
+        if !self.yes
+            && !dialoguer::Confirm::new()
+                .with_prompt("Apply these changes?")
+                .default(false)
+                .interact_opt()?
+                .unwrap_or_default()
+        {
+            println!("No changes were applied");
+            return Ok(());
+        }
+
+        let count = plan.changes.len();
+        plan.apply(&client, &manifest.app, app_id).await?;
+        println!(r#"Applied {count} change(s) to app "{}""#, manifest.app);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod apply_tests {
+    use super::*;
+    use cloud::MockCloudClientInterface;
+
+    fn manifest() -> CloudManifest {
+        toml::from_str(
+            r#"
+            app = "shop"
+            domain = "shop.example.com"
+            key_value_stores = ["cache"]
+
+            [variables]
+            greeting = "hello"
+            api_token = "s3cret"
+
+            [links.sqlite]
+            default = "orders"
+
+            [links.kv]
+            default = "sessions"
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn resource_label(app_id: Uuid, label: &str) -> ResourceLabel {
+        ResourceLabel {
+            app_id,
+            label: label.to_owned(),
+            app_name: Some("shop".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_manifest_formats_are_equivalent() {
+        let yaml: CloudManifest = serde_yaml::from_str(
+            r#"
+            app: shop
+            domain: shop.example.com
+            key_value_stores: [cache]
+            variables:
+              greeting: hello
+              api_token: s3cret
+            links:
+              sqlite:
+                default: orders
+              kv:
+                default: sessions
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest(), yaml);
+    }
+
+    #[test]
+    fn test_plan_creates_and_links_missing_resources() {
+        let app_id = Uuid::new_v4();
+        let plan = Plan::compute(
+            &manifest(),
+            app_id,
+            &[],
+            &[ResourceLinks::new("cache".to_owned(), vec![])],
+            &HashMap::from([("greeting", Some("hi"))]),
+            Some("shop.example.com"),
+        );
+
+        assert_eq!(
+            plan.changes,
+            vec![
+                Change::CreateResource {
+                    resource_type: ResourceType::Database,
+                    name: "orders".to_owned(),
+                },
+                Change::Link {
+                    resource_type: ResourceType::Database,
+                    label: "default".to_owned(),
+                    resource: "orders".to_owned(),
+                },
+                Change::CreateResource {
+                    resource_type: ResourceType::KeyValueStore,
+                    name: "sessions".to_owned(),
+                },
+                Change::Link {
+                    resource_type: ResourceType::KeyValueStore,
+                    label: "default".to_owned(),
+                    resource: "sessions".to_owned(),
+                },
+                Change::SetVariable {
+                    name: "api_token".to_owned(),
+                    value: "s3cret".to_owned(),
+                    exists: false,
+                },
+                Change::SetVariable {
+                    name: "greeting".to_owned(),
+                    value: "hello".to_owned(),
+                    exists: true,
+                },
+            ]
+        );
+        assert!(plan.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_plan_relinks_labels_and_skips_existing_links() {
+        let app_id = Uuid::new_v4();
+        let mut manifest = manifest();
+        manifest.variables.clear();
+        let databases = [ResourceLinks::new(
+            "orders".to_owned(),
+            vec![resource_label(app_id, "default")],
+        )];
+        let stores = [
+            ResourceLinks::new("cache".to_owned(), vec![]),
+            ResourceLinks::new("sessions".to_owned(), vec![]),
+            ResourceLinks::new(
+                "old-sessions".to_owned(),
+                vec![resource_label(app_id, "default")],
+            ),
+        ];
+
+        let plan = Plan::compute(
+            &manifest,
+            app_id,
+            &databases,
+            &stores,
+            &HashMap::new(),
+            None,
+        );
+
+        assert_eq!(
+            plan.changes,
+            vec![Change::Relink {
+                resource_type: ResourceType::KeyValueStore,
+                existing: resource_label(app_id, "default"),
+                from: "old-sessions".to_owned(),
+                to: "sessions".to_owned(),
+            }]
+        );
+        assert_eq!(1, plan.warnings.len());
+    }
+
+    #[test]
+    fn test_plan_skips_variables_with_the_same_value() {
+        let mut manifest = manifest();
+        manifest.links = ManifestLinks::default();
+        manifest.key_value_stores.clear();
+        let plan = Plan::compute(
+            &manifest,
+            Uuid::new_v4(),
+            &[],
+            &[],
+            &HashMap::from([("greeting", Some("hello")), ("api_token", None)]),
+            Some("shop.example.com"),
+        );
+
+        assert_eq!(
+            plan.changes,
+            vec![Change::SetVariable {
+                name: "api_token".to_owned(),
+                value: "s3cret".to_owned(),
+                exists: true,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_restores_link_if_relinking_fails() {
+        let app_id = Uuid::new_v4();
+        let plan = Plan {
+            changes: vec![Change::Relink {
+                resource_type: ResourceType::KeyValueStore,
+                existing: resource_label(app_id, "default"),
+                from: "old".to_owned(),
+                to: "new".to_owned(),
+            }],
+            warnings: vec![],
+        };
+
+        let mut mock = MockCloudClientInterface::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_remove_key_value_store_link()
+            .withf(|store, rl| store == "old" && rl.label == "default")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        mock.expect_create_key_value_store_link()
+            .withf(|store, _| store == "new")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Err(anyhow::anyhow!("store not found")));
+        mock.expect_create_key_value_store_link()
+            .withf(|store, rl| store == "old" && rl.label == "default")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+
+        let error = plan
+            .apply(&mock, "shop", app_id)
+            .await
+            .expect_err("relinking should have failed");
+        assert_eq!(
+            r#"Could not link key value store "new" to app "shop": store not found"#,
+            format!("{error:#}")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_relinks_label() -> Result<()> {
+        let app_id = Uuid::new_v4();
+        let plan = Plan {
+            changes: vec![Change::Relink {
+                resource_type: ResourceType::Database,
+                existing: resource_label(app_id, "default"),
+                from: "old".to_owned(),
+                to: "new".to_owned(),
+            }],
+            warnings: vec![],
+        };
+
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_remove_database_link()
+            .withf(|db, rl| db == "old" && rl.label == "default")
+            .returning(|_, _| Ok(()));
+        mock.expect_create_database_link()
+            .withf(move |db, rl| db == "new" && rl.label == "default" && rl.app_id == app_id)
+            .returning(|_, _| Ok(()));
+
+        plan.apply(&mock, "shop", app_id).await
+    }
+}
@@ -1,10 +1,13 @@
 use crate::commands::{apps_output::AppInfo, client_and_app_id, create_cloud_client, CommonArgs};
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
-use cloud::{CloudClientInterface, DEFAULT_APPLIST_PAGE_SIZE};
+use cloud::{CloudClientExt, CloudClientInterface, DEFAULT_APPLIST_PAGE_SIZE};
 use cloud_openapi::models::{AppItem, ValidationStatus};
+use uuid::Uuid;
 
-use super::apps_output::{print_app_info, print_app_list, OutputFormat};
+use super::apps_output::{
+    print_app_info, print_app_list, print_revision_list, OutputFormat, RevisionInfo,
+};
 
 #[derive(Parser, Debug)]
 #[clap(about = "Manage applications deployed to Fermyon Cloud")]
@@ -15,6 +18,10 @@ pub enum AppsCommand {
     Delete(DeleteCommand),
     /// Get details about a deployed app in Fermyon Cloud
     Info(InfoCommand),
+    /// List the revisions of an app deployed in Fermyon Cloud
+    Revisions(RevisionsCommand),
+    /// Make a previously deployed revision of an app the active one
+    Rollback(RollbackCommand),
 }
 
 #[derive(Parser, Debug)]
@@ -45,12 +52,36 @@ pub struct InfoCommand {
     format: OutputFormat,
 }
 
+#[derive(Parser, Debug)]
+pub struct RevisionsCommand {
+    /// Name of Spin app
+    pub app: String,
+    #[clap(flatten)]
+    common: CommonArgs,
+    /// Desired output format
+    #[clap(value_enum, long = "format", default_value = "plain")]
+    format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct RollbackCommand {
+    /// Name of Spin app
+    pub app: String,
+    /// Version of the app to make active, as listed by `spin cloud apps revisions`
+    #[clap(long = "to")]
+    pub version: String,
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
 impl AppsCommand {
     pub async fn run(self) -> Result<()> {
         match self {
             AppsCommand::List(cmd) => cmd.run().await,
             AppsCommand::Delete(cmd) => cmd.run().await,
             AppsCommand::Info(cmd) => cmd.run().await,
+            AppsCommand::Revisions(cmd) => cmd.run().await,
+            AppsCommand::Rollback(cmd) => cmd.run().await,
         }
     }
 }
@@ -114,6 +145,87 @@ impl InfoCommand {
     }
 }
 
+impl RevisionsCommand {
+    pub async fn run(self) -> Result<()> {
+        let (client, app_id) =
+            client_and_app_id(self.common.deployment_env_id.as_deref(), &self.app).await?;
+        let revisions = app_revisions(&client, &self.app, app_id).await?;
+        print_revision_list(revisions, self.format);
+        Ok(())
+    }
+}
+
+async fn app_revisions(
+    client: &impl CloudClientInterface,
+    app_name: &str,
+    app_id: Uuid,
+) -> Result<Vec<RevisionInfo>> {
+    let app = client
+        .get_app(app_id.to_string())
+        .await
+        .with_context(|| format!("Error: could not get details about {}", app_name))?;
+    let revisions = client
+        .list_app_revisions(app_id)
+        .await
+        .with_context(|| format!("Problem listing revisions of app named {}", app_name))?;
+
+    Ok(revisions
+        .into_iter()
+        .map(|r| {
+            let active = app
+                .channels
+                .iter()
+                .any(|c| c.active_revision_number.as_ref() == Some(&r.revision_number));
+            RevisionInfo::new(r.revision_number, active)
+        })
+        .collect())
+}
+
+impl RollbackCommand {
+    pub async fn run(self) -> Result<()> {
+        let (client, app_id) =
+            client_and_app_id(self.common.deployment_env_id.as_deref(), &self.app).await?;
+        rollback(&client, &self.app, app_id, &self.version).await?;
+        println!(
+            "App \"{}\" is now running version {}.",
+            &self.app, &self.version
+        );
+        Ok(())
+    }
+}
+
+async fn rollback(
+    client: &impl CloudClientInterface,
+    app_name: &str,
+    app_id: Uuid,
+    version: &str,
+) -> Result<()> {
+    let app = client
+        .get_app(app_id.to_string())
+        .await
+        .with_context(|| format!("Error: could not get details about {}", app_name))?;
+    // Apps deployed with `spin cloud deploy` have a single channel. If there
+    // are several, there is no telling which one is meant.
+    let channel = match app.channels.as_slice() {
+        [channel] => channel,
+        [] => bail!("App \"{app_name}\" has no channel to roll back"),
+        channels => bail!(
+            "App \"{app_name}\" has {} channels, so the one to roll back is ambiguous",
+            channels.len()
+        ),
+    };
+    let revision_id = client.get_revision_id(app_id, version).await?;
+    client
+        .set_active_revision(channel.id, revision_id)
+        .await
+        .with_context(|| {
+            format!(
+                "Problem rolling back app named {} to version {}",
+                app_name, version
+            )
+        })
+}
+
 fn domains_current_and_in_progress(app: &AppItem) -> (Option<&String>, Option<&String>) {
     let auto_domain = &app.subdomain;
     match &app.domain {
@@ -127,3 +239,117 @@ fn domains_current_and_in_progress(app: &AppItem) -> (Option<&String>, Option<&S
         None => (Some(auto_domain), None),
     }
 }
+
+#[cfg(test)]
+mod apps_tests {
+    use super::*;
+    use cloud::MockCloudClientInterface;
+    use cloud_openapi::models::{AppChannelListItem, RevisionItem, RevisionItemPage};
+
+    fn app(app_id: Uuid, channels: Vec<AppChannelListItem>) -> AppItem {
+        AppItem {
+            id: app_id,
+            name: "app".to_owned(),
+            channels,
+            ..Default::default()
+        }
+    }
+
+    fn channel(id: Uuid, active: &str) -> AppChannelListItem {
+        AppChannelListItem {
+            id,
+            active_revision_number: Some(active.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    // The generated model may have fields beyond those the tests need
+    #[allow(clippy::needless_update)]
+    fn revision(id: Uuid, app_id: Uuid, version: &str) -> RevisionItem {
+        RevisionItem {
+            id,
+            app_id,
+            revision_number: version.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn expect_revisions(mock: &mut MockCloudClientInterface, revisions: Vec<RevisionItem>) {
+        mock.expect_list_revisions().returning(move || {
+            Ok(RevisionItemPage {
+                items: revisions.clone(),
+                is_last_page: true,
+                ..Default::default()
+            })
+        });
+    }
+
+    #[tokio::test]
+    async fn test_revisions_of_the_app_are_listed_with_the_active_one() -> Result<()> {
+        let app_id = Uuid::new_v4();
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_get_app()
+            .returning(move |_| Ok(app(app_id, vec![channel(Uuid::new_v4(), "2")])));
+        expect_revisions(
+            &mut mock,
+            vec![
+                revision(Uuid::new_v4(), app_id, "1"),
+                revision(Uuid::new_v4(), Uuid::new_v4(), "3"),
+                revision(Uuid::new_v4(), app_id, "2"),
+            ],
+        );
+
+        let revisions = app_revisions(&mock, "app", app_id).await?;
+        assert_eq!(
+            vec![
+                RevisionInfo::new("1".to_owned(), false),
+                RevisionInfo::new("2".to_owned(), true),
+            ],
+            revisions
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rollback_activates_revision_on_the_app_channel() -> Result<()> {
+        let (app_id, channel_id, revision_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_get_app()
+            .returning(move |_| Ok(app(app_id, vec![channel(channel_id, "2")])));
+        expect_revisions(
+            &mut mock,
+            vec![
+                revision(revision_id, app_id, "1"),
+                revision(Uuid::new_v4(), app_id, "2"),
+            ],
+        );
+        mock.expect_set_active_revision()
+            .withf(move |channel, revision| channel == &channel_id && revision == &revision_id)
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        rollback(&mock, "app", app_id, "1").await
+    }
+
+    #[tokio::test]
+    async fn test_rollback_requires_exactly_one_channel() {
+        let app_id = Uuid::new_v4();
+        for (channels, expected) in [
+            (vec![], "App \"app\" has no channel to roll back"),
+            (
+                vec![channel(Uuid::new_v4(), "1"), channel(Uuid::new_v4(), "2")],
+                "App \"app\" has 2 channels, so the one to roll back is ambiguous",
+            ),
+        ] {
+            let mut mock = MockCloudClientInterface::new();
+            mock.expect_get_app()
+                .returning(move |_| Ok(app(app_id, channels.clone())));
+            mock.expect_set_active_revision().never();
+
+            let error = rollback(&mock, "app", app_id, "1")
+                .await
+                .expect_err("rollback should have failed");
+            assert_eq!(expected, error.to_string());
+        }
+    }
+}
@@ -61,6 +61,18 @@ impl Display for AppInfo {
     }
 }
 
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct RevisionInfo {
+    version: String,
+    active: bool,
+}
+
+impl RevisionInfo {
+    pub(crate) fn new(version: String, active: bool) -> Self {
+        Self { version, active }
+    }
+}
+
 pub(crate) fn print_app_list(apps: Vec<String>, format: OutputFormat) {
     match format {
         OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&apps).unwrap()),
@@ -80,3 +92,22 @@ pub(crate) fn print_app_info(app: AppInfo, format: OutputFormat) {
         OutputFormat::Plain => print!("{}", app),
     }
 }
+
+pub(crate) fn print_revision_list(revisions: Vec<RevisionInfo>, format: OutputFormat) {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&revisions).unwrap()),
+        OutputFormat::Plain => {
+            if revisions.is_empty() {
+                eprintln!("No revisions found");
+                return;
+            }
+            for revision in revisions {
+                if revision.active {
+                    println!("{} (active)", revision.version);
+                } else {
+                    println!("{}", revision.version);
+                }
+            }
+        }
+    }
+}
@@ -3,6 +3,7 @@ use chrono::{DateTime, Utc};
 use clap::Parser;
 use cloud::{
     client::{Client as CloudClient, ConnectionConfig},
+    models::CloudCapabilities,
     CloudClientExt, CloudClientInterface,
 };
 use oci_distribution::{token_cache, Reference, RegistryOperation};
@@ -14,7 +15,7 @@ use tokio::fs;
 use tracing::instrument;
 
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashSet},
     io::{self, Write},
     path::{Path, PathBuf},
     str::FromStr,
@@ -23,8 +24,18 @@ use url::Url;
 
 use crate::{
     commands::{
+        apps_output::OutputFormat,
         links_output::ResourceType,
-        variables::{get_variables, set_variables},
+        parallel::{combine_errors, run_all, MAX_CONCURRENT_REQUESTS},
+        secrets::SecretResolver,
+        variables::{
+            get_variables,
+            input::{
+                merge_variables, read_variables_file, read_variables_stdin, VariablesFormat,
+                CLOUD_ENV_FILE,
+            },
+            set_variables,
+        },
         DEFAULT_CLOUD_URL,
     },
     spin,
@@ -35,6 +46,8 @@ use crate::{
     opts::*,
 };
 
+mod journal;
+mod plan;
 mod resource;
 
 const DEVELOPER_CLOUD_FAQ: &str = "https://developer.fermyon.com/cloud/faq";
@@ -47,10 +60,6 @@ const SPIN_DEFAULT_KV_STORE: &str = "default";
 /// while the operation is in progress.
 const TOKEN_MUST_HAVE_REMAINING: chrono::TimeDelta = chrono::TimeDelta::minutes(5);
 
-// When we come to list features here, you can find consts for them in `spin_locked_app`
-// e.g. spin_locked_app::locked::SERVICE_CHAINING_KEY.
-const CLOUD_SUPPORTED_FEATURES: &[&str] = &[];
-
 /// Package and upload an application to the Fermyon Cloud.
 #[derive(Parser, Debug)]
 #[clap(about = "Package and upload an application to the Fermyon Cloud")]
@@ -118,9 +127,50 @@ pub struct DeployCommand {
     /// Set a variable (variable=value) in the deployed application.
     /// Any existing value will be overwritten.
     /// Can be used multiple times.
+    ///
+    /// Values may refer to secrets kept elsewhere, as env://NAME,
+    /// file://path or vault://path#field. Vault is read using the
+    /// VAULT_ADDR and VAULT_TOKEN environment variables. A value starting
+    /// with a backslash, such as \env://NAME, is set without the backslash
+    /// and is not resolved.
+    /// Each variable read from a reference is printed with the reference.
     #[clap(long = "variable", parse(try_from_str = parse_kv))]
     pub variables: Vec<(String, String)>,
 
+    /// Set variables in the deployed application from a file. The file may
+    /// be a .env file, or a JSON or TOML file of names to values, according
+    /// to its extension. Variables given with --variable take precedence.
+    ///
+    /// A .env.cloud file next to the application manifest is read too, with
+    /// the lowest precedence. Secret references in it are refused unless
+    /// --env-cloud-secrets is given.
+    #[clap(
+        name = "variables-from-file",
+        long = "variables-from-file",
+        conflicts_with = "variables-from-stdin"
+    )]
+    pub variables_file: Option<PathBuf>,
+
+    /// Set variables in the deployed application from stdin, in the .env
+    /// format unless --variables-format is given.
+    #[clap(
+        name = "variables-from-stdin",
+        long = "variables-from-stdin",
+        takes_value = false
+    )]
+    pub variables_stdin: bool,
+
+    /// Format of the variables read from a file or stdin
+    #[clap(value_enum, long = "variables-format")]
+    pub variables_format: Option<VariablesFormat>,
+
+    /// Read the secrets that values in the .env.cloud file refer to. Without
+    /// this, a .env.cloud file that refers to secrets is refused, so that a
+    /// checked-in file cannot make a deployment read local files or
+    /// environment variables.
+    #[clap(long = "env-cloud-secrets", takes_value = false)]
+    pub env_cloud_secrets: bool,
+
     /// Specifies how application labels (such as SQLite databases) should
     /// be linked if they are not already linked. This is intended for
     /// non-interactive environments such as release pipelines; therefore,
@@ -131,10 +181,21 @@ pub struct DeployCommand {
     /// will be created.
     #[clap(long = "link")]
     pub links: Vec<String>,
+
+    /// Print what the deployment would upload, create, link and set without
+    /// changing anything in Fermyon Cloud.
+    #[clap(name = "dry-run", long = "dry-run", takes_value = false)]
+    pub dry_run: bool,
+
+    /// Output format of the deployment plan printed by `--dry-run`
+    #[clap(value_enum, long = "format", requires = "dry-run")]
+    pub format: Option<OutputFormat>,
 }
 
 impl DeployCommand {
-    pub async fn run(self) -> Result<()> {
+    pub async fn run(mut self) -> Result<()> {
+        self.variables = self.resolve_variables()?;
+
         if self.build {
             self.run_spin_build().await?;
         }
@@ -146,44 +207,55 @@ impl DeployCommand {
             .map_err(|e| anyhow!("{:?}\n\nLearn more at {}", e, DEVELOPER_CLOUD_FAQ))
     }
 
-    fn resolve_app_source(&self) -> AppSource {
-        match (&self.app_source, &self.file_source, &self.registry_source) {
-            (None, None, None) => self.default_manifest_or_none(),
-            (Some(source), None, None) => Self::infer_source(source),
-            (None, Some(file), None) => Self::infer_file_source(file.to_owned()),
-            (None, None, Some(reference)) => AppSource::OciRegistry(reference.to_owned()),
-            _ => AppSource::unresolvable("More than one application source was specified"),
-        }
-    }
-
-    fn default_manifest_or_none(&self) -> AppSource {
-        let default_manifest = PathBuf::from(DEFAULT_MANIFEST_FILE);
-        if default_manifest.exists() {
-            AppSource::File(default_manifest)
-        } else {
-            AppSource::None
-        }
-    }
-
-    fn infer_source(source: &str) -> AppSource {
-        let path = PathBuf::from(source);
-        if path.exists() {
-            Self::infer_file_source(path)
-        } else if spin_oci::is_probably_oci_reference(source) {
-            AppSource::OciRegistry(source.to_owned())
-        } else {
-            AppSource::Unresolvable(format!("File or directory '{source}' not found. If you meant to load from a registry, use the `--from-registry` option."))
-        }
+    // Combines the variables from the .env.cloud file, those read from a file
+    // or stdin, and those given with --variable, in increasing precedence
+    fn resolve_variables(&self) -> Result<Vec<(String, String)>> {
+        let env_file = match self.resolve_app_source() {
+            AppSource::File(manifest) => manifest
+                .parent()
+                .map(|dir| dir.join(CLOUD_ENV_FILE))
+                .filter(|path| path.is_file()),
+            _ => None,
+        };
+        let env_file_variables = match &env_file {
+            Some(path) => {
+                eprintln!("Reading variables from {}", path.display());
+                let variables = read_variables_file(path, Some(VariablesFormat::Env))?;
+                let resolver = SecretResolver::default();
+                if let Some((name, _)) = variables
+                    .iter()
+                    .find(|(_, value)| !self.env_cloud_secrets && resolver.is_reference(value))
+                {
+                    bail!(
+                        "{} refers to a secret for variable '{name}'. Use --env-cloud-secrets to read it, or start the value with a backslash to set it as it is.",
+                        path.display()
+                    );
+                }
+                variables
+            }
+            None => vec![],
+        };
+        let read = match &self.variables_file {
+            Some(path) => read_variables_file(path, self.variables_format)?,
+            None if self.variables_stdin => read_variables_stdin(self.variables_format)?,
+            None => vec![],
+        };
+        Ok(merge_variables([
+            env_file_variables,
+            read,
+            self.variables.clone(),
+        ]))
     }
 
-    fn infer_file_source(path: impl Into<PathBuf>) -> AppSource {
-        match spin_common::paths::resolve_manifest_file_path(path.into()) {
-            Ok(file) => AppSource::File(file),
-            Err(e) => AppSource::Unresolvable(e.to_string()),
-        }
+    fn resolve_app_source(&self) -> AppSource {
+        AppSource::resolve(
+            self.app_source.as_deref(),
+            self.file_source.as_deref(),
+            self.registry_source.as_deref(),
+        )
     }
 
-    async fn deploy_cloud(self, login_connection: LoginConnection) -> Result<()> {
+    async fn deploy_cloud(mut self, login_connection: LoginConnection) -> Result<()> {
         let connection_config = ConnectionConfig {
             url: login_connection.url.to_string(),
             insecure: login_connection.danger_accept_invalid_certs,
@@ -197,14 +269,11 @@ impl DeployCommand {
 
         let application = self.load_cloud_app(dir.path()).await?;
 
-        validate_cloud_app(&application)?;
+        let capabilities = cloud_capabilities(&login_connection, &client).await;
+        validate_cloud_app(&application, &capabilities)?;
         self.validate_deployment_environment(&application, &client)
             .await?;
 
-        let digest = self
-            .push_oci(application.clone(), connection_config.clone())
-            .await?;
-
         let name = sanitize_app_name(application.name()?);
         let storage_id = format!("oci://{}", name);
         let version = sanitize_app_version(application.version()?);
@@ -215,82 +284,76 @@ impl DeployCommand {
         }
         let db_labels = application.sqlite_databases();
 
-        println!("Deploying...");
-
-        // Create or update app
-        let app_id = match client.get_app_id(&name).await? {
-            Some(app_id) => {
-                resource::create_and_link_resources_for_existing_app(
-                    &client,
-                    &name,
-                    app_id,
-                    db_labels,
-                    kv_labels,
-                    interact.as_ref(),
-                )
-                .await?;
-                client
-                    .add_revision(storage_id.clone(), version.clone())
-                    .await?;
-                // We have already checked that default kv store exists
-                for kv in self.key_values {
-                    client
-                        .add_key_value_pair(
-                            Some(app_id),
-                            SPIN_DEFAULT_KV_STORE.to_string(),
-                            kv.0,
-                            kv.1,
-                        )
-                        .await
-                        .context("Problem creating key/value")?;
-                }
+        let existing_app_id = client.get_app_id(&name).await?;
+        let resources = match resource::plan_resources(
+            &client,
+            &name,
+            existing_app_id.is_some(),
+            db_labels,
+            kv_labels,
+            interact.as_ref(),
+        )
+        .await?
+        {
+            Some(resources) => resources,
+            None => return Ok(()), // User canceled terminal interaction
+        };
 
-                set_variables(&client, app_id, &self.variables).await?;
+        if self.dry_run {
+            let plan = plan::DeploymentPlan::new(
+                name,
+                version,
+                existing_app_id.is_none(),
+                oci_reference(&application, &connection_config.url)?,
+                &resources,
+                &self.key_values,
+                &self.variables,
+            );
+            return plan.print(self.format.unwrap_or(OutputFormat::Plain));
+        }
 
-                app_id
-            }
-            None => {
-                let resources_to_link = match resource::create_resources_for_new_app(
-                    &client,
-                    &name,
-                    db_labels,
-                    kv_labels,
-                    interact.as_ref(),
-                )
-                .await?
-                {
-                    Some(dbs) => dbs,
-                    // TODO: Clean up created databases and kv stores
-                    None => return Ok(()), // User canceled terminal interaction
-                };
-                let app_id = client
-                    .add_app(&name, &storage_id)
-                    .await
-                    .context("Unable to create app")?;
+        // Secrets are read only once the deployment is going ahead, so that a
+        // dry run never reads them
+        self.variables = SecretResolver::default()
+            .resolve_variables(std::mem::take(&mut self.variables))
+            .await?;
 
-                // Now that the app has been created, we can link resources to it.
-                resource::link_resources(&client, &name, app_id, resources_to_link).await?;
-                client
-                    .add_revision(storage_id.clone(), version.clone())
-                    .await
-                    .context(format!("Unable to upload {}", version.clone()))?;
-
-                // Have already checked that default kv store exists
-                for kv in self.key_values {
-                    client
-                        .add_key_value_pair(
-                            Some(app_id),
-                            SPIN_DEFAULT_KV_STORE.to_string(),
-                            kv.0,
-                            kv.1,
-                        )
-                        .await
-                        .context("Problem creating key/value")?;
-                }
+        let digest = self
+            .push_oci(application.clone(), connection_config.clone())
+            .await?;
 
-                set_variables(&client, app_id, &self.variables).await?;
+        println!("Deploying...");
 
-                app_id
+        // Record what the deployment creates, so that it can be removed again
+        // if a later step fails or the user interrupts the deployment
+        let journal = journal::Journal::new(&client);
+        let deployment = self.create_or_update_app(
+            &journal,
+            &name,
+            &storage_id,
+            &version,
+            existing_app_id,
+            resources,
+        );
+        tokio::pin!(deployment);
+        // The first Ctrl-C lets the step in progress finish, so that what it
+        // creates is recorded and can be removed. A second one exits at once.
+        let deployed = tokio::select! {
+            result = &mut deployment => result,
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!("Cancelling the deployment after the current step. Press Ctrl-C again to exit now.");
+                journal.cancel();
+                tokio::select! {
+                    result = &mut deployment => result,
+                    _ = tokio::signal::ctrl_c() => std::process::exit(130),
+                }
+            }
+        };
+        let app_id = match deployed {
+            Ok(app_id) => app_id,
+            Err(e) => {
+                journal.compensate().await;
+                return Err(e);
             }
         };
 
@@ -318,6 +381,77 @@ impl DeployCommand {
         Ok(())
     }
 
+    // Creates the app or registers the new revision of an existing one,
+    // along with its resources, key/value pairs and variables
+    async fn create_or_update_app(
+        &self,
+        client: &journal::Journal<'_, impl CloudClientInterface>,
+        name: &str,
+        storage_id: &str,
+        version: &str,
+        existing_app_id: Option<uuid::Uuid>,
+        resources: Vec<resource::ResourcePlan>,
+    ) -> Result<uuid::Uuid> {
+        client.ensure_not_cancelled()?;
+        let app_id = match existing_app_id {
+            Some(app_id) => {
+                resource::create_and_link_resources_for_existing_app(
+                    client, name, app_id, resources,
+                )
+                .await?;
+                client.ensure_not_cancelled()?;
+                client
+                    .add_revision(storage_id.to_owned(), version.to_owned())
+                    .await?;
+                app_id
+            }
+            None => {
+                let resources_to_link =
+                    resource::create_resources_for_new_app(client, resources).await?;
+                client.ensure_not_cancelled()?;
+                let app_id = client
+                    .add_app(name, storage_id)
+                    .await
+                    .context("Unable to create app")?;
+                client.ensure_not_cancelled()?;
+
+                // Now that the app has been created, we can link resources to it.
+                resource::link_resources(client, name, app_id, resources_to_link).await?;
+                client.ensure_not_cancelled()?;
+                client
+                    .add_revision(storage_id.to_owned(), version.to_owned())
+                    .await
+                    .context(format!("Unable to upload {}", version))?;
+                app_id
+            }
+        };
+
+        // Have already checked that default kv store exists
+        let key_values = self.key_values.iter().map(|(key, value)| async move {
+            client
+                .add_key_value_pair(
+                    Some(app_id),
+                    SPIN_DEFAULT_KV_STORE.to_string(),
+                    key.clone(),
+                    value.clone(),
+                )
+                .await
+                .with_context(|| format!("Problem creating key/value {key}"))
+        });
+        let (key_values, variables) = futures::join!(
+            run_all(key_values, MAX_CONCURRENT_REQUESTS),
+            set_variables(client, app_id, &self.variables)
+        );
+        combine_errors(
+            [key_values.err(), variables.err()]
+                .into_iter()
+                .flatten()
+                .collect(),
+        )?;
+
+        Ok(app_id)
+    }
+
     fn interaction_strategy(&self) -> anyhow::Result<Box<dyn resource::InteractionStrategy>> {
         if self.links.is_empty() {
             return Ok(Box::new(resource::Interactive));
@@ -328,50 +462,7 @@ impl DeployCommand {
     }
 
     async fn load_cloud_app(&self, working_dir: &Path) -> Result<DeployableApp, anyhow::Error> {
-        let app_source = self.resolve_app_source();
-
-        let locked_app = match &app_source {
-            AppSource::File(app_file) => {
-                spin_loader::from_file(
-                    &app_file,
-                    spin_loader::FilesMountStrategy::Copy(working_dir.to_owned()),
-                    None,
-                )
-                .await?
-            }
-            AppSource::OciRegistry(reference) => {
-                let mut oci_client = spin_oci::Client::new(false, None)
-                    .await
-                    .context("cannot create registry client")?;
-
-                spin_oci::OciLoader::new(working_dir)
-                    .load_app(&mut oci_client, reference)
-                    .await?
-            }
-            AppSource::None => {
-                anyhow::bail!("Default file '{DEFAULT_MANIFEST_FILE}' not found.");
-            }
-            AppSource::Unresolvable(err) => {
-                anyhow::bail!("{err}");
-            }
-        };
-
-        let unsupported_triggers = locked_app
-            .triggers
-            .iter()
-            .filter(|t| t.trigger_type != "http")
-            .map(|t| format!("'{}'", t.trigger_type))
-            .collect::<Vec<_>>();
-        if !unsupported_triggers.is_empty() {
-            bail!(
-                "Non-HTTP triggers are not supported - app uses {}",
-                unsupported_triggers.join(", ")
-            );
-        }
-
-        if let Err(unsupported) = locked_app.ensure_needs_only("http", CLOUD_SUPPORTED_FEATURES) {
-            bail!("This app requires features that are not yet available on Fermyon Cloud: {unsupported}");
-        }
+        let locked_app = self.resolve_app_source().load(working_dir).await?;
 
         let locked_app = ensure_http_base_set(locked_app);
         let locked_app = ensure_plugin_version_set(locked_app);
@@ -451,19 +542,7 @@ impl DeployCommand {
     ) -> Result<Option<String>> {
         let mut client = spin_oci::Client::new(connection_config.insecure, None).await?;
 
-        let cloud_url =
-            Url::parse(connection_config.url.as_str()).context("Unable to parse cloud URL")?;
-        let cloud_host = cloud_url
-            .host_str()
-            .context("Unable to derive host from cloud URL")?;
-        let cloud_registry_host = format!("registry.{cloud_host}");
-
-        let reference = format!(
-            "{}/{}:{}",
-            cloud_registry_host,
-            &sanitize_app_name(application.name()?),
-            &sanitize_app_version(application.version()?)
-        );
+        let reference = oci_reference(&application, &connection_config.url)?;
 
         let oci_ref = Reference::try_from(reference.as_ref())
             .context(format!("Could not parse reference '{reference}'"))?;
@@ -503,11 +582,30 @@ impl DeployCommand {
     }
 }
 
+// The reference in the Fermyon Cloud registry that an app version is pushed to
+fn oci_reference(application: &DeployableApp, cloud_url: &str) -> Result<String> {
+    let cloud_url = Url::parse(cloud_url).context("Unable to parse cloud URL")?;
+    let cloud_host = cloud_url
+        .host_str()
+        .context("Unable to derive host from cloud URL")?;
+    let cloud_registry_host = format!("registry.{cloud_host}");
+
+    Ok(format!(
+        "{}/{}:{}",
+        cloud_registry_host,
+        &sanitize_app_name(application.name()?),
+        &sanitize_app_version(application.version()?)
+    ))
+}
+
 // Spin now allows HTTP apps to omit the base path, but Cloud
 // doesn't yet like this. This works around that by defaulting
-// base if not set. (We don't check trigger type because by the
-// time this is called we know it's HTTP.)
+// base if not set.
 fn ensure_http_base_set(mut locked_app: locked::LockedApp) -> locked::LockedApp {
+    if !locked_app.triggers.iter().any(|t| t.trigger_type == "http") {
+        return locked_app;
+    }
+
     if let Some(trigger) = locked_app
         .metadata
         .entry("trigger")
@@ -528,8 +626,9 @@ fn ensure_plugin_version_set(mut locked_app: locked::LockedApp) -> locked::Locke
     locked_app
 }
 
+/// Where an application is loaded from: a manifest, or a registry reference
 #[derive(Debug, PartialEq, Eq)]
-enum AppSource {
+pub(crate) enum AppSource {
     None,
     File(PathBuf),
     OciRegistry(String),
@@ -537,10 +636,84 @@ enum AppSource {
 }
 
 impl AppSource {
+    /// Resolves the source given by the `--from`, `--from-file` and
+    /// `--from-registry` options, defaulting to the manifest in the current
+    /// directory
+    pub(crate) fn resolve(
+        source: Option<&str>,
+        file: Option<&Path>,
+        registry: Option<&str>,
+    ) -> Self {
+        match (source, file, registry) {
+            (None, None, None) => Self::default_manifest_or_none(),
+            (Some(source), None, None) => Self::infer_source(source),
+            (None, Some(file), None) => Self::infer_file_source(file),
+            (None, None, Some(reference)) => Self::OciRegistry(reference.to_owned()),
+            _ => Self::unresolvable("More than one application source was specified"),
+        }
+    }
+
+    fn default_manifest_or_none() -> Self {
+        let default_manifest = PathBuf::from(DEFAULT_MANIFEST_FILE);
+        if default_manifest.exists() {
+            Self::File(default_manifest)
+        } else {
+            Self::None
+        }
+    }
+
+    fn infer_source(source: &str) -> Self {
+        let path = PathBuf::from(source);
+        if path.exists() {
+            Self::infer_file_source(path)
+        } else if spin_oci::is_probably_oci_reference(source) {
+            Self::OciRegistry(source.to_owned())
+        } else {
+            Self::Unresolvable(format!("File or directory '{source}' not found. If you meant to load from a registry, use the `--from-registry` option."))
+        }
+    }
+
+    fn infer_file_source(path: impl Into<PathBuf>) -> Self {
+        match spin_common::paths::resolve_manifest_file_path(path.into()) {
+            Ok(file) => Self::File(file),
+            Err(e) => Self::Unresolvable(e.to_string()),
+        }
+    }
+
     fn unresolvable(message: impl Into<String>) -> Self {
         Self::Unresolvable(message.into())
     }
 
+    /// Loads the application, copying its files into the working directory
+    pub(crate) async fn load(&self, working_dir: &Path) -> Result<locked::LockedApp> {
+        let locked_app = match self {
+            Self::File(app_file) => {
+                spin_loader::from_file(
+                    &app_file,
+                    spin_loader::FilesMountStrategy::Copy(working_dir.to_owned()),
+                    None,
+                )
+                .await?
+            }
+            Self::OciRegistry(reference) => {
+                let mut oci_client = spin_oci::Client::new(false, None)
+                    .await
+                    .context("cannot create registry client")?;
+
+                spin_oci::OciLoader::new(working_dir)
+                    .load_app(&mut oci_client, reference)
+                    .await?
+            }
+            Self::None => {
+                anyhow::bail!("Default file '{DEFAULT_MANIFEST_FILE}' not found.");
+            }
+            Self::Unresolvable(err) => {
+                anyhow::bail!("{err}");
+            }
+        };
+        Ok(locked_app)
+    }
+
     async fn build(&self) -> anyhow::Result<()> {
         match self {
             Self::File(manifest_path) => {
@@ -606,13 +779,66 @@ fn sanitize_app_version(tag: &str) -> String {
     sanitized.replace(' ', "")
 }
 
-fn validate_cloud_app(app: &DeployableApp) -> Result<()> {
+// The capabilities configured for the deployment environment take precedence
+// over those advertised by the cloud instance. Instances that advertise none
+// are assumed to support only HTTP triggers.
+async fn cloud_capabilities(
+    login_connection: &LoginConnection,
+    client: &impl CloudClientInterface,
+) -> CloudCapabilities {
+    if let Some(capabilities) = &login_connection.capabilities {
+        return capabilities.clone();
+    }
+    match client.get_capabilities().await {
+        Ok(capabilities) => capabilities.unwrap_or_default(),
+        Err(e) => {
+            tracing::warn!("Could not get cloud capabilities, assuming defaults: {e:?}");
+            CloudCapabilities::default()
+        }
+    }
+}
+
+fn validate_cloud_app(app: &DeployableApp, capabilities: &CloudCapabilities) -> Result<()> {
     check_safe_app_name(app.name()?)?;
     ensure!(!app.components().is_empty(), "No components in spin.toml!");
+    check_capabilities(app, capabilities)?;
     check_no_duplicate_routes(app)?;
     Ok(())
 }
 
+fn check_capabilities(app: &DeployableApp, capabilities: &CloudCapabilities) -> Result<()> {
+    let trigger_types = app
+        .0
+        .triggers
+        .iter()
+        .map(|t| t.trigger_type.as_str())
+        .collect::<BTreeSet<_>>();
+
+    let unsupported_triggers = trigger_types
+        .iter()
+        .filter(|t| !capabilities.supports_trigger_type(t))
+        .map(|t| format!("'{t}'"))
+        .collect::<Vec<_>>();
+    if !unsupported_triggers.is_empty() {
+        bail!(
+            "This Fermyon Cloud instance does not support the trigger types used by the app: {}",
+            unsupported_triggers.join(", ")
+        );
+    }
+
+    let host_features = capabilities
+        .host_features
+        .iter()
+        .map(|f| f.as_str())
+        .collect::<Vec<_>>();
+    for trigger_type in trigger_types {
+        if let Err(unsupported) = app.0.ensure_needs_only(trigger_type, &host_features) {
+            bail!("This app requires features that are not yet available on Fermyon Cloud: {unsupported}");
+        }
+    }
+    Ok(())
+}
+
 fn check_no_duplicate_routes(app: &DeployableApp) -> Result<()> {
     let (_, _, duplicates) = app.http_routes()?;
     if duplicates.is_empty() {
@@ -1146,7 +1372,13 @@ mod test {
             deployment_env_id: None,
             key_values: vec![],
             variables: vec![],
+            variables_file: None,
+            variables_stdin: false,
+            variables_format: None,
+            env_cloud_secrets: false,
             links: vec![],
+            dry_run: false,
+            format: None,
         }
     }
 
@@ -1205,6 +1437,75 @@ mod test {
         assert_eq!(crate::VERSION, version);
     }
 
+    fn app_with_triggers(trigger_types: &[&str]) -> DeployableApp {
+        let triggers = trigger_types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                serde_json::json!({
+                    "id": format!("trigger{i}"),
+                    "trigger_type": t,
+                    "trigger_config": {},
+                })
+            })
+            .collect::<Vec<_>>();
+        let locked_app = serde_json::from_value(serde_json::json!({
+            "spin_lock_version": 1,
+            "metadata": {},
+            "variables": {},
+            "triggers": triggers,
+            "components": [],
+        }))
+        .unwrap();
+        DeployableApp(locked_app)
+    }
+
+    #[test]
+    fn trigger_types_must_be_supported_by_cloud() {
+        let app = app_with_triggers(&["http", "redis"]);
+        check_capabilities(&app, &CloudCapabilities::default())
+            .expect_err("redis triggers should not be supported by default");
+
+        let capabilities = CloudCapabilities {
+            trigger_types: vec!["http".to_owned(), "redis".to_owned()],
+            host_features: vec![],
+        };
+        check_capabilities(&app, &capabilities).expect("redis triggers should be supported");
+    }
+
+    #[tokio::test]
+    async fn advertised_capabilities_are_used_unless_configured() {
+        let advertised = CloudCapabilities {
+            trigger_types: vec!["http".to_owned(), "redis".to_owned()],
+            host_features: vec!["key_value".to_owned()],
+        };
+        let mut client = cloud::MockCloudClientInterface::new();
+        let returned = advertised.clone();
+        client
+            .expect_get_capabilities()
+            .times(1)
+            .returning(move || Ok(Some(returned.clone())));
+        let mut login_connection = LoginConnection {
+            url: Url::parse("https://cloud.fermyon.com/").unwrap(),
+            danger_accept_invalid_certs: false,
+            token: "token".to_owned(),
+            refresh_token: None,
+            expiration: None,
+            capabilities: None,
+        };
+        assert_eq!(
+            advertised,
+            cloud_capabilities(&login_connection, &client).await
+        );
+
+        // Configured capabilities are used without asking the cloud instance
+        login_connection.capabilities = Some(CloudCapabilities::default());
+        assert_eq!(
+            CloudCapabilities::default(),
+            cloud_capabilities(&login_connection, &client).await
+        );
+    }
+
     fn string_set(strs: &[&str]) -> HashSet<String> {
         strs.iter().map(|s| s.to_string()).collect()
     }
@@ -1228,9 +1529,10 @@ mod test {
             .withf(|db, rlabel| db == "excel" && rlabel.is_none())
             .returning(|_, _| Ok(()));
 
-        let databases_to_link = resource::create_resources_for_new_app(
+        let plan = resource::plan_resources(
             &client,
             "test:script-new-app",
+            false,
             db_labels,
             HashSet::new(),
             &linkages,
@@ -1238,6 +1540,9 @@ mod test {
         .await
         .unwrap()
         .unwrap();
+        let databases_to_link = resource::create_resources_for_new_app(&client, plan)
+            .await
+            .unwrap();
         assert_eq!(2, databases_to_link.len());
 
         client
@@ -1287,9 +1592,10 @@ mod test {
             .withf(|s, rlabel| s == "excel" && rlabel.is_none())
             .returning(|_, _| Ok(()));
 
-        let stores_to_link = resource::create_resources_for_new_app(
+        let plan = resource::plan_resources(
             &client,
             "test:script-new-app",
+            false,
             HashSet::new(),
             kv_labels,
             &linkages,
@@ -1297,6 +1603,9 @@ mod test {
         .await
         .unwrap()
         .unwrap();
+        let stores_to_link = resource::create_resources_for_new_app(&client, plan)
+            .await
+            .unwrap();
         assert_eq!(2, stores_to_link.len());
 
         client
@@ -1357,9 +1666,10 @@ mod test {
             .withf(|db, rlabel| db == "excel" && rlabel.is_none())
             .returning(|_, _| Ok(()));
 
-        let stores_to_link = resource::create_resources_for_new_app(
+        let plan = resource::plan_resources(
             &client,
             "test:script-new-app",
+            false,
             db_labels,
             kv_labels,
             &linkages,
@@ -1367,6 +1677,9 @@ mod test {
         .await
         .unwrap()
         .unwrap();
+        let stores_to_link = resource::create_resources_for_new_app(&client, plan)
+            .await
+            .unwrap();
         assert_eq!(4, stores_to_link.len());
 
         client
@@ -1395,4 +1708,113 @@ mod test {
         .await
         .unwrap();
     }
+
+    #[tokio::test]
+    async fn new_resource_selected_for_several_labels_is_created_once() {
+        let db_labels = string_set(&["default", "finance"]);
+        let links = ["sqlite:default=shared", "sqlite:finance=shared"];
+        let linkages = parse_linkage_specs(&links).unwrap();
+
+        let mut client = cloud::MockCloudClientInterface::new();
+        client.expect_get_databases().returning(|_| Ok(vec![]));
+        client
+            .expect_create_database()
+            .withf(|db, rlabel| db == "shared" && rlabel.is_none())
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let plan = resource::plan_resources(
+            &client,
+            "test:script-new-app",
+            false,
+            db_labels,
+            HashSet::new(),
+            &linkages,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(1, plan.iter().filter(|r| r.create).count());
+
+        let databases_to_link = resource::create_resources_for_new_app(&client, plan)
+            .await
+            .unwrap();
+        assert_eq!(2, databases_to_link.len());
+    }
+
+    #[tokio::test]
+    async fn planning_does_not_change_existing_app_resources() {
+        let app_id = uuid::Uuid::new_v4();
+        let db_labels = string_set(&["default", "finance"]);
+        let links = ["sqlite:default=def-o-rama", "sqlite:finance=excel"];
+        let linkages = parse_linkage_specs(&links).unwrap();
+
+        // No create or link expectations: the mock panics if planning mutates anything
+        let mut client = cloud::MockCloudClientInterface::new();
+        client.expect_get_databases().returning(move |_| {
+            Ok(vec![cloud_openapi::models::Database::new(
+                "def-o-rama".to_string(),
+                vec![cloud_openapi::models::ResourceLabel {
+                    app_id,
+                    label: "default".to_string(),
+                    app_name: Some("test:script-existing-app".to_string()),
+                }],
+            )])
+        });
+
+        let plan = resource::plan_resources(
+            &client,
+            "test:script-existing-app",
+            true,
+            db_labels,
+            HashSet::new(),
+            &linkages,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(1, plan.len());
+        assert_eq!("finance", plan[0].link.label);
+        assert_eq!("excel", plan[0].link.resource_name);
+        assert!(plan[0].create);
+    }
+
+    #[test]
+    fn env_cloud_secret_references_require_opt_in() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manifest = dir.path().join("spin.toml");
+        std::fs::write(&manifest, "")?;
+        std::fs::write(
+            dir.path().join(CLOUD_ENV_FILE),
+            "a=env://HOME\nb='\\file:///srv/data'\n",
+        )?;
+        let mut cmd = deploy_cmd_for_test_file("unused");
+        cmd.file_source = Some(manifest);
+
+        let error = cmd
+            .resolve_variables()
+            .expect_err("should have refused the secret reference")
+            .to_string();
+        assert!(error.contains("variable 'a'"), "{error}");
+
+        cmd.env_cloud_secrets = true;
+        assert_eq!(
+            vec![
+                ("a".to_owned(), "env://HOME".to_owned()),
+                ("b".to_owned(), "\\file:///srv/data".to_owned()),
+            ],
+            cmd.resolve_variables()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn format_requires_dry_run() {
+        DeployCommand::try_parse_from(["deploy", "--format", "json"])
+            .expect_err("--format should require --dry-run");
+        let cmd = DeployCommand::try_parse_from(["deploy", "--dry-run", "--format", "json"])
+            .expect("--format should be accepted with --dry-run");
+        assert!(cmd.dry_run);
+        assert_eq!(Some(OutputFormat::Json), cmd.format);
+    }
 }
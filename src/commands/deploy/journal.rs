@@ -0,0 +1,477 @@
+//! A cloud client wrapper that records the resources a deployment creates, so
+//! that they can be removed again if the deployment fails or is cancelled
+//! part way through
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use cloud::{
+    models::{CloudCapabilities, KeyValueKeyPage, SqlQueryResult},
+    CloudClientInterface,
+};
+use cloud_openapi::models::{
+    AppItem, AppItemPage, Database, DeviceCodeItem, GetAppLogsVm, GetAppRawLogsVm,
+    KeyValueStoreItem, ResourceLabel, RevisionItemPage, TokenInfo,
+};
+use uuid::Uuid;
+
+/// A change made by a deployment that can be undone
+#[derive(Debug, PartialEq)]
+enum JournalEntry {
+    App(Uuid),
+    Database(String),
+    KeyValueStore(String),
+    DatabaseLink(String, ResourceLabel),
+    KeyValueStoreLink(String, ResourceLabel),
+}
+
+pub(super) struct Journal<'a, C> {
+    client: &'a C,
+    entries: Mutex<Vec<JournalEntry>>,
+    // Set once a revision has been registered. From then on the app runs with
+    // the recorded changes, so they must no longer be undone.
+    committed: AtomicBool,
+    cancelled: AtomicBool,
+}
+
+impl<'a, C: CloudClientInterface> Journal<'a, C> {
+    pub(super) fn new(client: &'a C) -> Self {
+        Self {
+            client,
+            entries: Mutex::new(vec![]),
+            committed: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    /// Asks the deployment to stop before its next step. Steps are not
+    /// interrupted, so that whatever they create is recorded.
+    pub(super) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub(super) fn ensure_not_cancelled(&self) -> Result<()> {
+        anyhow::ensure!(
+            !self.cancelled.load(Ordering::SeqCst),
+            "Deployment cancelled"
+        );
+        Ok(())
+    }
+
+    fn record(&self, entry: JournalEntry) {
+        if !self.committed.load(Ordering::SeqCst) {
+            self.entries.lock().unwrap().push(entry);
+        }
+    }
+
+    /// Forgets the recorded changes and stops recording, so that nothing the
+    /// deployment has done so far is undone
+    fn commit(&self) {
+        self.committed.store(true, Ordering::SeqCst);
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Undoes the recorded changes, most recent first. Failures are reported
+    /// but do not stop the remaining changes from being undone.
+    pub(super) async fn compensate(&self) {
+        let entries = std::mem::take(&mut *self.entries.lock().unwrap());
+        if entries.is_empty() {
+            return;
+        }
+        eprintln!("Removing resources created by the incomplete deployment...");
+        for entry in entries.into_iter().rev() {
+            let (description, result) = match entry {
+                JournalEntry::App(id) => (
+                    format!("app {id}"),
+                    self.client.remove_app(id.to_string()).await,
+                ),
+                JournalEntry::Database(name) => (
+                    format!("database \"{name}\""),
+                    self.client.delete_database(name.clone()).await,
+                ),
+                JournalEntry::KeyValueStore(name) => (
+                    format!("key value store \"{name}\""),
+                    self.client.delete_key_value_store(&name).await,
+                ),
+                JournalEntry::DatabaseLink(database, label) => (
+                    format!(
+                        "link from database \"{database}\" to label \"{}\"",
+                        label.label
+                    ),
+                    self.client.remove_database_link(&database, label).await,
+                ),
+                JournalEntry::KeyValueStoreLink(store, label) => (
+                    format!(
+                        "link from key value store \"{store}\" to label \"{}\"",
+                        label.label
+                    ),
+                    self.client.remove_key_value_store_link(&store, label).await,
+                ),
+            };
+            if let Err(e) = result {
+                eprintln!("Could not remove {description}: {e:?}");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<C: CloudClientInterface> CloudClientInterface for Journal<'_, C> {
+    async fn create_device_code(&self, client_id: Uuid) -> Result<DeviceCodeItem> {
+        self.client.create_device_code(client_id).await
+    }
+
+    async fn login(&self, token: String) -> Result<TokenInfo> {
+        self.client.login(token).await
+    }
+
+    async fn refresh_token(&self, token: String, refresh_token: String) -> Result<TokenInfo> {
+        self.client.refresh_token(token, refresh_token).await
+    }
+
+    async fn get_capabilities(&self) -> Result<Option<CloudCapabilities>> {
+        self.client.get_capabilities().await
+    }
+
+    async fn add_app(&self, name: &str, storage_id: &str) -> Result<Uuid> {
+        let id = self.client.add_app(name, storage_id).await?;
+        self.record(JournalEntry::App(id));
+        Ok(id)
+    }
+
+    async fn remove_app(&self, id: String) -> Result<()> {
+        self.client.remove_app(id).await
+    }
+
+    async fn get_app(&self, id: String) -> Result<AppItem> {
+        self.client.get_app(id).await
+    }
+
+    async fn list_apps(&self, page_size: i32, page_index: Option<i32>) -> Result<AppItemPage> {
+        self.client.list_apps(page_size, page_index).await
+    }
+
+    async fn app_logs(&self, id: String) -> Result<GetAppLogsVm> {
+        self.client.app_logs(id).await
+    }
+
+    async fn app_logs_raw(
+        &self,
+        id: String,
+        max_lines: Option<i32>,
+        since: Option<String>,
+    ) -> Result<GetAppRawLogsVm> {
+        self.client.app_logs_raw(id, max_lines, since).await
+    }
+
+    async fn add_revision(&self, app_storage_id: String, revision_number: String) -> Result<()> {
+        self.client
+            .add_revision(app_storage_id, revision_number)
+            .await?;
+        self.commit();
+        Ok(())
+    }
+
+    async fn list_revisions(&self) -> Result<RevisionItemPage> {
+        self.client.list_revisions().await
+    }
+
+    async fn list_revisions_next(&self, previous: &RevisionItemPage) -> Result<RevisionItemPage> {
+        self.client.list_revisions_next(previous).await
+    }
+
+    async fn set_active_revision(&self, channel_id: Uuid, revision_id: Uuid) -> Result<()> {
+        self.client
+            .set_active_revision(channel_id, revision_id)
+            .await
+    }
+
+    async fn add_key_value_pair(
+        &self,
+        app_id: Option<Uuid>,
+        store_name: String,
+        key: String,
+        value: String,
+    ) -> Result<()> {
+        self.client
+            .add_key_value_pair(app_id, store_name, key, value)
+            .await
+    }
+
+    async fn get_key_value_pair(&self, store_name: String, key: String) -> Result<Option<String>> {
+        self.client.get_key_value_pair(store_name, key).await
+    }
+
+    async fn list_key_value_keys(
+        &self,
+        store_name: String,
+        prefix: Option<String>,
+        page_size: i32,
+        page_index: Option<i32>,
+    ) -> Result<KeyValueKeyPage> {
+        self.client
+            .list_key_value_keys(store_name, prefix, page_size, page_index)
+            .await
+    }
+
+    async fn delete_key_value_pair(&self, store_name: String, key: String) -> Result<()> {
+        self.client.delete_key_value_pair(store_name, key).await
+    }
+
+    async fn create_key_value_store(
+        &self,
+        store_name: &str,
+        resource_label: Option<ResourceLabel>,
+    ) -> Result<()> {
+        self.client
+            .create_key_value_store(store_name, resource_label)
+            .await?;
+        // Deleting the store also removes any link created along with it
+        self.record(JournalEntry::KeyValueStore(store_name.to_owned()));
+        Ok(())
+    }
+
+    async fn delete_key_value_store(&self, store_name: &str) -> Result<()> {
+        self.client.delete_key_value_store(store_name).await
+    }
+
+    async fn rename_key_value_store(&self, store_name: &str, new_name: &str) -> Result<()> {
+        self.client
+            .rename_key_value_store(store_name, new_name)
+            .await
+    }
+
+    async fn get_key_value_stores(&self, app_id: Option<Uuid>) -> Result<Vec<KeyValueStoreItem>> {
+        self.client.get_key_value_stores(app_id).await
+    }
+
+    async fn create_key_value_store_link(
+        &self,
+        key_value_store: &str,
+        resource_label: ResourceLabel,
+    ) -> Result<()> {
+        self.client
+            .create_key_value_store_link(key_value_store, resource_label.clone())
+            .await?;
+        self.record(JournalEntry::KeyValueStoreLink(
+            key_value_store.to_owned(),
+            resource_label,
+        ));
+        Ok(())
+    }
+
+    async fn remove_key_value_store_link(
+        &self,
+        key_value_store: &str,
+        resource_label: ResourceLabel,
+    ) -> Result<()> {
+        self.client
+            .remove_key_value_store_link(key_value_store, resource_label)
+            .await
+    }
+
+    async fn add_variable_pair(&self, app_id: Uuid, variable: String, value: String) -> Result<()> {
+        self.client.add_variable_pair(app_id, variable, value).await
+    }
+
+    async fn delete_variable_pair(&self, app_id: Uuid, variable: String) -> Result<()> {
+        self.client.delete_variable_pair(app_id, variable).await
+    }
+
+    async fn get_variable_pairs(&self, app_id: Uuid) -> Result<Vec<String>> {
+        self.client.get_variable_pairs(app_id).await
+    }
+
+    async fn create_database(
+        &self,
+        name: String,
+        resource_label: Option<ResourceLabel>,
+    ) -> Result<()> {
+        self.client
+            .create_database(name.clone(), resource_label)
+            .await?;
+        // Deleting the database also removes any link created along with it
+        self.record(JournalEntry::Database(name));
+        Ok(())
+    }
+
+    async fn execute_sql(&self, database: String, statement: String) -> Result<SqlQueryResult> {
+        self.client.execute_sql(database, statement).await
+    }
+
+    async fn delete_database(&self, name: String) -> Result<()> {
+        self.client.delete_database(name).await
+    }
+
+    async fn get_databases(&self, app_id: Option<Uuid>) -> Result<Vec<Database>> {
+        self.client.get_databases(app_id).await
+    }
+
+    async fn create_database_link(
+        &self,
+        database: &str,
+        resource_label: ResourceLabel,
+    ) -> Result<()> {
+        self.client
+            .create_database_link(database, resource_label.clone())
+            .await?;
+        self.record(JournalEntry::DatabaseLink(
+            database.to_owned(),
+            resource_label,
+        ));
+        Ok(())
+    }
+
+    async fn remove_database_link(
+        &self,
+        database: &str,
+        resource_label: ResourceLabel,
+    ) -> Result<()> {
+        self.client
+            .remove_database_link(database, resource_label)
+            .await
+    }
+
+    async fn rename_database(&self, database: String, new_name: String) -> Result<()> {
+        self.client.rename_database(database, new_name).await
+    }
+}
+
+#[cfg(test)]
+mod journal_tests {
+    use super::*;
+    use mockall::Sequence;
+
+    fn label(app_id: Uuid, label: &str) -> ResourceLabel {
+        ResourceLabel {
+            app_id,
+            label: label.to_owned(),
+            app_name: Some("app".to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    async fn compensates_recorded_changes_in_reverse_order() {
+        let app_id = Uuid::new_v4();
+        let mut client = cloud::MockCloudClientInterface::new();
+        client.expect_create_database().returning(|_, _| Ok(()));
+        client
+            .expect_create_key_value_store()
+            .returning(|_, _| Ok(()));
+        client.expect_add_app().returning(move |_, _| Ok(app_id));
+        client
+            .expect_create_database_link()
+            .returning(|_, _| Ok(()));
+
+        let mut seq = Sequence::new();
+        client
+            .expect_remove_database_link()
+            .withf(|db, l| db == "db" && l.label == "default")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        client
+            .expect_remove_app()
+            .withf(move |id| id == &app_id.to_string())
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+        client
+            .expect_delete_key_value_store()
+            .withf(|s| s == "store")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+        client
+            .expect_delete_database()
+            .withf(|db| db == "db")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(()));
+
+        let journal = Journal::new(&client);
+        journal
+            .create_database("db".to_owned(), None)
+            .await
+            .unwrap();
+        journal.create_key_value_store("store", None).await.unwrap();
+        let id = journal.add_app("app", "oci://app").await.unwrap();
+        journal
+            .create_database_link("db", label(id, "default"))
+            .await
+            .unwrap();
+
+        journal.compensate().await;
+        assert!(journal.entries.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn registered_revision_is_not_compensated() {
+        let app_id = Uuid::new_v4();
+        let mut client = cloud::MockCloudClientInterface::new();
+        client.expect_create_database().returning(|_, _| Ok(()));
+        client
+            .expect_create_database_link()
+            .returning(|_, _| Ok(()));
+        client.expect_add_revision().returning(|_, _| Ok(()));
+        client
+            .expect_create_key_value_store_link()
+            .returning(|_, _| Ok(()));
+        client.expect_remove_database_link().never();
+        client.expect_delete_database().never();
+        client.expect_remove_key_value_store_link().never();
+
+        let journal = Journal::new(&client);
+        journal
+            .create_database("db".to_owned(), None)
+            .await
+            .unwrap();
+        journal
+            .create_database_link("db", label(app_id, "default"))
+            .await
+            .unwrap();
+        journal
+            .add_revision("app".to_owned(), "1.0.0".to_owned())
+            .await
+            .unwrap();
+        journal
+            .create_key_value_store_link("store", label(app_id, "default"))
+            .await
+            .unwrap();
+
+        journal.compensate().await;
+    }
+
+    #[tokio::test]
+    async fn failed_calls_are_not_recorded() {
+        let mut client = cloud::MockCloudClientInterface::new();
+        client
+            .expect_create_database()
+            .returning(|_, _| Err(anyhow::anyhow!("database already exists")));
+        client
+            .expect_create_key_value_store_link()
+            .returning(|_, _| Ok(()));
+
+        let journal = Journal::new(&client);
+        journal
+            .create_database("db".to_owned(), None)
+            .await
+            .unwrap_err();
+        let app_id = Uuid::new_v4();
+        journal
+            .create_key_value_store_link("store", label(app_id, "default"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![JournalEntry::KeyValueStoreLink(
+                "store".to_owned(),
+                label(app_id, "default")
+            )],
+            *journal.entries.lock().unwrap()
+        );
+    }
+}
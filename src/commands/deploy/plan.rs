@@ -0,0 +1,117 @@
+//! The plan printed by `spin cloud deploy --dry-run`, describing what a
+//! deployment would push, create, link and set without doing any of it
+use anyhow::Result;
+use serde::{Serialize, Serializer};
+
+use crate::commands::{
+    apps_output::OutputFormat,
+    links_output::{ResourceGroupBy, ResourceType},
+};
+
+use super::resource::ResourcePlan;
+
+#[derive(Serialize)]
+pub(super) struct DeploymentPlan {
+    app: String,
+    version: String,
+    /// Whether the app is deployed for the first time
+    new_app: bool,
+    oci_reference: String,
+    resources: Vec<PlannedResource>,
+    /// Keys set in the default key value store. Values are never printed.
+    key_values: Vec<String>,
+    /// Names of the variables set. Values are never printed.
+    variables: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PlannedResource {
+    label: String,
+    #[serde(rename = "type", serialize_with = "serialize_resource_type")]
+    resource_type: ResourceType,
+    resource: String,
+    create: bool,
+}
+
+impl DeploymentPlan {
+    pub(super) fn new(
+        app: String,
+        version: String,
+        new_app: bool,
+        oci_reference: String,
+        resources: &[ResourcePlan],
+        key_values: &[(String, String)],
+        variables: &[(String, String)],
+    ) -> Self {
+        let resources = resources
+            .iter()
+            .map(|r| PlannedResource {
+                label: r.link.label.clone(),
+                resource_type: r.link.resource_type,
+                resource: r.link.resource_name.clone(),
+                create: r.create,
+            })
+            .collect();
+        Self {
+            app,
+            version,
+            new_app,
+            oci_reference,
+            resources,
+            key_values: key_values.iter().map(|(k, _)| k.clone()).collect(),
+            variables: variables.iter().map(|(k, _)| k.clone()).collect(),
+        }
+    }
+
+    pub(super) fn print(&self, format: OutputFormat) -> Result<()> {
+        match format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(self)?),
+            OutputFormat::Plain => print!("{}", self),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for DeploymentPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Dry run: nothing will be uploaded, created, linked or set."
+        )?;
+        if self.new_app {
+            writeln!(f, "Create app \"{}\"", self.app)?;
+        } else {
+            writeln!(f, "Update app \"{}\"", self.app)?;
+        }
+        writeln!(
+            f,
+            "Upload version {} to {}",
+            self.version, self.oci_reference
+        )?;
+        for r in &self.resources {
+            let resource_type = r.resource_type;
+            if r.create {
+                writeln!(f, "Create {resource_type} \"{}\"", r.resource)?;
+            }
+            writeln!(
+                f,
+                "Link {resource_type} \"{}\" to the label \"{}\"",
+                r.resource, r.label
+            )?;
+        }
+        for key in &self.key_values {
+            writeln!(f, "Set key \"{key}\" in the default key value store")?;
+        }
+        for variable in &self.variables {
+            writeln!(f, "Set variable \"{variable}\"")?;
+        }
+        Ok(())
+    }
+}
+
+fn serialize_resource_type<S: Serializer>(
+    resource_type: &ResourceType,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&ResourceGroupBy::Resource(*resource_type))
+}
@@ -6,6 +6,7 @@ use cloud_openapi::models::ResourceLabel;
 
 use crate::commands::links_output::ResourceLinks;
 use crate::commands::links_output::ResourceType;
+use crate::commands::parallel::{run_all, MAX_CONCURRENT_REQUESTS};
 use crate::random_name::RandomNameGenerator;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
@@ -21,10 +22,12 @@ pub(super) enum ResourceSelection {
     Cancelled,
 }
 
-/// Whether a resource has already been linked or not
-enum ExistingAppResourceSelection {
-    NotYetLinked(ResourceSelection),
-    AlreadyLinked,
+/// What deploying an app will do to satisfy one of its resource labels,
+/// decided before anything is created or linked
+pub(super) struct ResourcePlan {
+    pub(super) link: LinkageSpec,
+    /// Whether the resource does not exist yet and has to be created
+    pub(super) create: bool,
 }
 
 async fn get_resources(
@@ -47,40 +50,6 @@ async fn get_resources(
     }
 }
 
-async fn get_resource_selection_for_existing_app(
-    name: &str,
-    client: &impl CloudClientInterface,
-    resource_label: &ResourceLabel,
-    interact: &dyn InteractionStrategy,
-    resource_type: ResourceType,
-) -> Result<ExistingAppResourceSelection> {
-    let resources = get_resources(client, resource_type).await?;
-    if resources
-        .iter()
-        .any(|d| d.has_link(&resource_label.label, resource_label.app_name.as_deref()))
-    {
-        return Ok(ExistingAppResourceSelection::AlreadyLinked);
-    }
-    let selection = interact.prompt_resource_selection(
-        name,
-        &resource_label.label,
-        resources,
-        resource_type,
-    )?;
-    Ok(ExistingAppResourceSelection::NotYetLinked(selection))
-}
-
-async fn get_resource_selection_for_new_app(
-    name: &str,
-    client: &impl CloudClientInterface,
-    label: &str,
-    interact: &dyn InteractionStrategy,
-    resource_type: ResourceType,
-) -> Result<ResourceSelection> {
-    let resources = get_resources(client, resource_type).await?;
-    interact.prompt_resource_selection(name, label, resources, resource_type)
-}
-
 pub(super) struct Interactive;
 
 pub(super) trait InteractionStrategy {
@@ -247,138 +216,146 @@ impl Scripted {
     }
 }
 
-// Loops through an app's manifest and creates resources.
-// Returns a list of linkages that should be resolved
-// once the app is created.
+// Loops through an app's manifest and selects a resource for each label
+// that is not yet linked, without creating or linking anything.
 // Returns None if the user canceled terminal interaction
-pub(super) async fn create_resources_for_new_app(
+pub(super) async fn plan_resources(
     client: &impl CloudClientInterface,
     app_name: &str,
+    app_exists: bool,
     db_labels: HashSet<String>,
     kv_labels: HashSet<String>,
     interact: &dyn InteractionStrategy,
-) -> anyhow::Result<Option<Vec<LinkageSpec>>> {
-    let mut resources_to_link: Vec<LinkageSpec> = Vec::new();
-    let db_label_types = db_labels.into_iter().map(|l| (l, ResourceType::Database));
-    let kv_label_types = kv_labels
+) -> anyhow::Result<Option<Vec<ResourcePlan>>> {
+    let mut db_labels = db_labels.into_iter().collect::<Vec<_>>();
+    db_labels.sort();
+    let mut kv_labels = kv_labels.into_iter().collect::<Vec<_>>();
+    kv_labels.sort();
+    let label_types = db_labels
         .into_iter()
-        .map(|l| (l, ResourceType::KeyValueStore));
-    let label_types = db_label_types
-        .chain(kv_label_types)
-        .collect::<Vec<(_, _)>>();
+        .map(|l| (l, ResourceType::Database))
+        .chain(
+            kv_labels
+                .into_iter()
+                .map(|l| (l, ResourceType::KeyValueStore)),
+        );
+
+    let mut plan = Vec::new();
+    // Several labels may select the same new resource, which must only be created once
+    let mut to_create = HashSet::<(String, ResourceType)>::new();
     for (label, resource_type) in label_types {
-        let resource = match get_resource_selection_for_new_app(
-            app_name,
-            client,
-            &label,
-            interact,
-            resource_type,
-        )
-        .await?
-        {
-            ResourceSelection::Existing(r) => r,
-            ResourceSelection::New(r) => {
-                println!("Creating {resource_type} named '{r}'");
-                match resource_type {
-                    ResourceType::Database => {
-                        client
-                            .create_database(r.clone(), None)
-                            .await
-                            .context("Could not create database")?;
-                    }
-                    ResourceType::KeyValueStore => {
-                        client
-                            .create_key_value_store(&r, None)
-                            .await
-                            .context("Could not create key value store")?;
-                    }
-                }
-                r
+        let mut resources = get_resources(client, resource_type).await?;
+        if app_exists && resources.iter().any(|r| r.has_link(&label, Some(app_name))) {
+            continue;
+        }
+        // Resources chosen to be created for earlier labels can be shared
+        for (name, _) in to_create.iter().filter(|(_, t)| *t == resource_type) {
+            if !resources.iter().any(|r| &r.name == name) {
+                resources.push(ResourceLinks::new(name.clone(), vec![]));
             }
-            // User canceled terminal interaction
-            ResourceSelection::Cancelled => return Ok(None),
-        };
-        resources_to_link.push(LinkageSpec::new(label, resource, resource_type));
+        }
+        let (resource, create) =
+            match interact.prompt_resource_selection(app_name, &label, resources, resource_type)? {
+                ResourceSelection::Existing(r) => (r, false),
+                ResourceSelection::New(r) => {
+                    let create = to_create.insert((r.clone(), resource_type));
+                    (r, create)
+                }
+                // User canceled terminal interaction
+                ResourceSelection::Cancelled => return Ok(None),
+            };
+        plan.push(ResourcePlan {
+            link: LinkageSpec::new(label, resource, resource_type),
+            create,
+        });
     }
-    Ok(Some(resources_to_link))
+    Ok(Some(plan))
 }
 
-// Loops through an updated app's manifest and creates and links any newly referenced resources.
-// Returns None if the user canceled terminal interaction
+// Creates the resources planned for an app that does not exist yet.
+// Returns a list of linkages that should be resolved
+// once the app is created.
+pub(super) async fn create_resources_for_new_app(
+    client: &impl CloudClientInterface,
+    plan: Vec<ResourcePlan>,
+) -> anyhow::Result<Vec<LinkageSpec>> {
+    let to_create = plan.iter().filter(|p| p.create).map(|p| &p.link);
+    for link in to_create.clone() {
+        println!(
+            "Creating {} named '{}'",
+            link.resource_type, link.resource_name
+        );
+    }
+    let tasks = to_create.map(|link| async move {
+        match link.resource_type {
+            ResourceType::Database => client
+                .create_database(link.resource_name.clone(), None)
+                .await
+                .context("Could not create database"),
+            ResourceType::KeyValueStore => client
+                .create_key_value_store(&link.resource_name, None)
+                .await
+                .context("Could not create key value store"),
+        }
+    });
+    run_all(tasks, MAX_CONCURRENT_REQUESTS).await?;
+    Ok(plan.into_iter().map(|p| p.link).collect())
+}
+
+// Creates and links the resources planned for an already existing app.
+// Several labels may share a new resource, so resources are all created
+// before any existing ones are linked.
 pub(super) async fn create_and_link_resources_for_existing_app(
     client: &impl CloudClientInterface,
     app_name: &str,
     app_id: uuid::Uuid,
-    db_labels: HashSet<String>,
-    kv_labels: HashSet<String>,
-    interact: &dyn InteractionStrategy,
-) -> anyhow::Result<Option<()>> {
-    let db_label_types = db_labels.into_iter().map(|l| (l, ResourceType::Database));
-    let kv_label_types = kv_labels
+    plan: Vec<ResourcePlan>,
+) -> anyhow::Result<()> {
+    let (to_create, to_link): (Vec<_>, Vec<_>) = plan.into_iter().partition(|p| p.create);
+    let tasks = to_create
         .into_iter()
-        .map(|l| (l, ResourceType::KeyValueStore));
-    let label_types = db_label_types
-        .chain(kv_label_types)
-        .collect::<Vec<(_, _)>>();
-    for (label, resource_type) in label_types {
-        let resource_label = ResourceLabel {
-            app_id,
-            label,
-            app_name: Some(app_name.to_string()),
-        };
-        if let ExistingAppResourceSelection::NotYetLinked(selection) =
-            get_resource_selection_for_existing_app(
-                app_name,
-                client,
-                &resource_label,
-                interact,
-                resource_type,
-            )
-            .await?
-        {
-            match selection {
-                // User canceled terminal interaction
-                ResourceSelection::Cancelled => return Ok(None),
-                ResourceSelection::New(resource) => match resource_type {
-                    ResourceType::Database => {
-                        client
-                            .create_database(resource, Some(resource_label))
-                            .await?;
-                    }
-                    ResourceType::KeyValueStore => {
-                        client
-                            .create_key_value_store(&resource, Some(resource_label))
-                            .await?;
-                    }
-                },
-                ResourceSelection::Existing(r) => match resource_type {
-                    ResourceType::Database => {
-                        client
-                            .create_database_link(&r, resource_label)
-                            .await
-                            .with_context(|| {
-                                format!(
-                                    r#"Could not link {resource_type} "{}" to app "{}""#,
-                                    r, app_name,
-                                )
-                            })?;
-                    }
-                    ResourceType::KeyValueStore => {
-                        client
-                            .create_key_value_store_link(&r, resource_label)
-                            .await
-                            .with_context(|| {
-                                format!(
-                                    r#"Could not link {resource_type} "{}" to app "{}""#,
-                                    r, app_name,
-                                )
-                            })?;
-                    }
-                },
+        .map(|ResourcePlan { link, .. }| async move {
+            let resource_label = ResourceLabel {
+                app_id,
+                label: link.label,
+                app_name: Some(app_name.to_string()),
+            };
+            let r = link.resource_name;
+            match link.resource_type {
+                ResourceType::Database => client.create_database(r, Some(resource_label)).await,
+                ResourceType::KeyValueStore => {
+                    client
+                        .create_key_value_store(&r, Some(resource_label))
+                        .await
+                }
             }
-        }
-    }
-    Ok(Some(()))
+        });
+    run_all(tasks, MAX_CONCURRENT_REQUESTS).await?;
+
+    let tasks = to_link
+        .into_iter()
+        .map(|ResourcePlan { link, .. }| async move {
+            let resource_type = link.resource_type;
+            let resource_label = ResourceLabel {
+                app_id,
+                label: link.label,
+                app_name: Some(app_name.to_string()),
+            };
+            let r = link.resource_name;
+            match resource_type {
+                ResourceType::Database => client.create_database_link(&r, resource_label).await,
+                ResourceType::KeyValueStore => {
+                    client.create_key_value_store_link(&r, resource_label).await
+                }
+            }
+            .with_context(|| {
+                format!(
+                    r#"Could not link {resource_type} "{}" to app "{}""#,
+                    r, app_name,
+                )
+            })
+        });
+    run_all(tasks, MAX_CONCURRENT_REQUESTS).await
 }
 
 pub(super) async fn link_resources(
@@ -387,7 +364,7 @@ pub(super) async fn link_resources(
     app_id: Uuid,
     linkages: Vec<LinkageSpec>,
 ) -> anyhow::Result<()> {
-    for link in linkages {
+    let tasks = linkages.into_iter().map(|link| async move {
         let resource_label = ResourceLabel {
             label: link.label,
             app_id,
@@ -398,25 +375,133 @@ pub(super) async fn link_resources(
                 client
                     .create_database_link(&link.resource_name, resource_label)
                     .await
-                    .with_context(|| {
-                        format!(
-                            r#"Failed to link {} "{}" to app "{}""#,
-                            link.resource_type, link.resource_name, app_name
-                        )
-                    })?;
             }
             ResourceType::KeyValueStore => {
                 client
                     .create_key_value_store_link(&link.resource_name, resource_label)
                     .await
-                    .with_context(|| {
-                        format!(
-                            r#"Failed to link {} "{}" to app "{}""#,
-                            link.resource_type, link.resource_name, app_name
-                        )
-                    })?;
             }
         }
+        .with_context(|| {
+            format!(
+                r#"Failed to link {} "{}" to app "{}""#,
+                link.resource_type, link.resource_name, app_name
+            )
+        })
+    });
+    run_all(tasks, MAX_CONCURRENT_REQUESTS).await
+}
+
+#[cfg(test)]
+mod resource_tests {
+    use super::*;
+    use cloud::MockCloudClientInterface;
+
+    #[tokio::test]
+    async fn test_shared_new_resource_is_created_before_it_is_linked() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_create_database()
+            .withf(|name, label| name == "new-db" && label.as_ref().unwrap().label == "first")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        mock.expect_create_database_link()
+            .withf(|name, label| name == "new-db" && label.label == "second")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+
+        let plan = vec![
+            ResourcePlan {
+                link: LinkageSpec::new(
+                    "second".to_owned(),
+                    "new-db".to_owned(),
+                    ResourceType::Database,
+                ),
+                create: false,
+            },
+            ResourcePlan {
+                link: LinkageSpec::new(
+                    "first".to_owned(),
+                    "new-db".to_owned(),
+                    ResourceType::Database,
+                ),
+                create: true,
+            },
+        ];
+        create_and_link_resources_for_existing_app(&mock, "app", Uuid::new_v4(), plan).await
+    }
+
+    // Creates a new resource for the first label, and links every later label
+    // to the last resource it is offered
+    struct ShareNew;
+
+    impl InteractionStrategy for ShareNew {
+        fn prompt_resource_selection(
+            &self,
+            _name: &str,
+            label: &str,
+            resources: Vec<ResourceLinks>,
+            _resource_type: ResourceType,
+        ) -> Result<ResourceSelection> {
+            match resources.last() {
+                Some(resource) if label != "first" => {
+                    Ok(ResourceSelection::Existing(resource.name.clone()))
+                }
+                _ => Ok(ResourceSelection::New("new-db".to_owned())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_planned_new_resources_are_offered_for_later_labels() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_get_databases().returning(|_| Ok(vec![]));
+
+        let labels = ["first", "second"].map(String::from).into_iter().collect();
+        let plan = plan_resources(&mock, "app", false, labels, HashSet::new(), &ShareNew)
+            .await?
+            .expect("planning should not have been cancelled");
+        let planned = plan
+            .iter()
+            .map(|p| {
+                (
+                    p.link.label.as_str(),
+                    p.link.resource_name.as_str(),
+                    p.create,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![("first", "new-db", true), ("second", "new-db", false)],
+            planned
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_failed_links_are_reported() {
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_create_database_link()
+            .returning(|_, _| Err(anyhow!("database is gone")));
+        mock.expect_create_key_value_store_link()
+            .returning(|_, _| Err(anyhow!("store is gone")));
+
+        let linkages = vec![
+            LinkageSpec::new("db".to_owned(), "db1".to_owned(), ResourceType::Database),
+            LinkageSpec::new(
+                "kv".to_owned(),
+                "kv1".to_owned(),
+                ResourceType::KeyValueStore,
+            ),
+        ];
+        let error = link_resources(&mock, "app", Uuid::new_v4(), linkages)
+            .await
+            .expect_err("linking should have failed");
+        let message = error.to_string();
+        assert!(message.starts_with("2 operations failed"), "{message}");
+        assert!(message.contains("database is gone"), "{message}");
+        assert!(message.contains("store is gone"), "{message}");
     }
-    Ok(())
 }
@@ -3,12 +3,20 @@ use crate::commands::links_output::{
     ResourceType,
 };
 use crate::commands::links_target::ResourceTarget;
+use crate::commands::parallel::MAX_CONCURRENT_REQUESTS;
 use crate::commands::{create_cloud_client, disallow_empty, CommonArgs};
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{prelude::BASE64_STANDARD, Engine};
 use clap::{Parser, ValueEnum};
-use cloud::CloudClientInterface;
+use cloud::{CloudClientExt, CloudClientInterface};
 use cloud_openapi::models::KeyValueStoreItem;
 use spin_common::arg_parser::parse_kv;
+use std::io::Read;
+use std::path::PathBuf;
+
+use self::transfer::{export_pairs, format_pairs, import_pairs, parse_pairs, TransferFormat};
+
+mod transfer;
 
 #[derive(Parser, Debug)]
 #[clap(about = "Manage Fermyon Cloud key value stores")]
@@ -21,6 +29,16 @@ pub enum KeyValueCommand {
     List(ListCommand),
     /// Set a key value pair in a store
     Set(SetCommand),
+    /// Get the value of a key in a store
+    Get(GetCommand),
+    /// List the keys in a store
+    Keys(KeysCommand),
+    /// Delete keys from a store
+    DeleteKey(DeleteKeyCommand),
+    /// Set the key value pairs in a file in a store
+    Import(ImportCommand),
+    /// Write the key value pairs in a store to a file
+    Export(ExportCommand),
     /// Rename a key value store. All existing links will automatically link to the store's new name.
     Rename(RenameCommand),
 }
@@ -81,25 +99,145 @@ impl From<GroupBy> for ResourceGroupBy {
     }
 }
 
+/// The key value store that a command works on, either by name or by the
+/// label it is linked to in an app
 #[derive(Parser, Debug)]
-pub struct SetCommand {
+pub struct StoreTarget {
     /// The name of the key value store
     #[clap(name = "STORE", short = 's', long = "store", value_parser = clap::builder::ValueParser::new(disallow_empty), required_unless_present_all = ["LABEL", "APP"], conflicts_with_all = &["LABEL", "APP"])]
     pub store: Option<String>,
 
-    /// Label of the key value store to set pairs in
+    /// Label of the key value store
     #[clap(name = "LABEL", short = 'l', long = "label", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "APP", required_unless_present = "STORE")]
     pub label: Option<String>,
 
     /// App to which label relates
     #[clap(name = "APP", short = 'a', long = "app", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "LABEL", required_unless_present = "STORE")]
     pub app: Option<String>,
+}
+
+impl StoreTarget {
+    /// Finds the name of the targeted store
+    pub async fn store_name(&self, client: &impl CloudClientInterface) -> Result<String> {
+        let target = ResourceTarget::from_inputs(&self.store, &self.label, &self.app)?;
+        let stores = client
+            .get_key_value_stores(None)
+            .await
+            .context("Problem fetching key value stores")?;
+        Ok(target
+            .find_in(to_resource_links(stores), ResourceType::KeyValueStore)?
+            .name)
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct SetCommand {
+    #[clap(flatten)]
+    target: StoreTarget,
 
     /// A key/value pair (key=value) to set in the store. Any existing value will be overwritten.
-    /// Can be used multiple times.
+    /// Can be used multiple times. To read the value from a file, prefix the filename with @
+    /// e.g. key=@cert.pem, or use key=- to read it from stdin. A value that starts with @ can
+    /// be given literally by doubling the @ e.g. key=@@value.
     #[clap(parse(try_from_str = parse_kv))]
     pub key_values: Vec<(String, String)>,
 
+    /// Store values base64-encoded, e.g. to set binary data read from files
+    #[clap(long = "base64", takes_value = false)]
+    pub base64: bool,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct GetCommand {
+    #[clap(flatten)]
+    target: StoreTarget,
+
+    /// The key to get the value of
+    #[clap(value_parser = clap::builder::ValueParser::new(disallow_empty))]
+    pub key: String,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct KeysCommand {
+    #[clap(flatten)]
+    target: StoreTarget,
+
+    /// Only list keys that start with this prefix
+    #[clap(long = "prefix")]
+    pub prefix: Option<String>,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct DeleteKeyCommand {
+    #[clap(flatten)]
+    target: StoreTarget,
+
+    /// The keys to delete from the store
+    #[clap(required = true, value_parser = clap::builder::ValueParser::new(disallow_empty))]
+    pub keys: Vec<String>,
+
+    /// Skips prompt to confirm deletion of the keys
+    #[clap(short = 'y', long = "yes", takes_value = false)]
+    yes: bool,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportCommand {
+    #[clap(flatten)]
+    target: StoreTarget,
+
+    /// The file of key value pairs to import, or "-" to read from stdin.
+    /// Values are text, so binary values must be base64-encoded.
+    pub file: PathBuf,
+
+    /// Format of the file. If omitted, the format is guessed from the file
+    /// name: ".ndjson" or ".jsonl" files are NDJSON, ".env" files are in the
+    /// .env format, and other files are JSON.
+    #[clap(value_enum, long = "format")]
+    pub format: Option<TransferFormat>,
+
+    /// The maximum number of pairs to set at once
+    #[clap(long = "concurrency", default_value_t = MAX_CONCURRENT_REQUESTS)]
+    pub concurrency: usize,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportCommand {
+    #[clap(flatten)]
+    target: StoreTarget,
+
+    /// Only export keys that start with this prefix
+    #[clap(long = "prefix")]
+    pub prefix: Option<String>,
+
+    /// The file to write the pairs to. If omitted, the pairs are written to stdout.
+    #[clap(short = 'o', long = "output")]
+    pub output: Option<PathBuf>,
+
+    /// Format to write the pairs in. If omitted, the format is guessed from
+    /// the name of the output file, in the same way as for `import`.
+    #[clap(value_enum, long = "format")]
+    pub format: Option<TransferFormat>,
+
+    /// The maximum number of values to get at once
+    #[clap(long = "concurrency", default_value_t = MAX_CONCURRENT_REQUESTS)]
+    pub concurrency: usize,
+
     #[clap(flatten)]
     common: CommonArgs,
 }
@@ -135,6 +273,26 @@ impl KeyValueCommand {
                 let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
                 cmd.run(client).await
             }
+            KeyValueCommand::Get(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
+            KeyValueCommand::Keys(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
+            KeyValueCommand::DeleteKey(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
+            KeyValueCommand::Import(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
+            KeyValueCommand::Export(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
             KeyValueCommand::Rename(cmd) => {
                 let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
                 cmd.run(client).await
@@ -218,17 +376,15 @@ impl ListCommand {
 
 impl SetCommand {
     pub async fn run(&self, client: impl CloudClientInterface) -> Result<()> {
-        let target = ResourceTarget::from_inputs(&self.store, &self.label, &self.app)?;
-        let stores = client
-            .get_key_value_stores(None)
-            .await
-            .context("Problem fetching key value stores")?;
-        let store = target
-            .find_in(to_resource_links(stores), ResourceType::KeyValueStore)?
-            .name;
+        if self.key_values.iter().filter(|(_, v)| v == "-").count() > 1 {
+            bail!("Only one value can be read from stdin");
+        }
+        let store = self.target.store_name(&client).await?;
         for (key, value) in &self.key_values {
+            let resolved = read_value(value, self.base64)
+                .with_context(|| format!("Could not read the value of key '{key}'"))?;
             client
-                .add_key_value_pair(None, store.clone(), key.clone(), value.clone())
+                .add_key_value_pair(None, store.clone(), key.clone(), resolved)
                 .await
                 .with_context(|| {
                     format!(
@@ -241,6 +397,154 @@ impl SetCommand {
     }
 }
 
+// Resolves a value given to `set`, reading it from a file or stdin if asked to
+fn read_value(value: &str, base64: bool) -> Result<String> {
+    let bytes = if let Some(literal) = value.strip_prefix("@@") {
+        format!("@{literal}").into_bytes()
+    } else if let Some(path) = value.strip_prefix('@') {
+        std::fs::read(path).with_context(|| format!("could not read file at '{path}'"))?
+    } else if value == "-" {
+        let mut bytes = vec![];
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .context("could not read from stdin")?;
+        bytes
+    } else {
+        value.as_bytes().to_vec()
+    };
+
+    if base64 {
+        Ok(BASE64_STANDARD.encode(bytes))
+    } else {
+        String::from_utf8(bytes).map_err(|_| {
+            anyhow!("the value is not valid UTF-8 text. Use --base64 to set binary data.")
+        })
+    }
+}
+
+impl GetCommand {
+    pub async fn run(&self, client: impl CloudClientInterface) -> Result<()> {
+        let store = self.target.store_name(&client).await?;
+        let value = client
+            .get_key_value_pair(store.clone(), self.key.clone())
+            .await
+            .with_context(|| format!("Error getting key '{}' from store '{store}'", self.key))?
+            .with_context(|| format!(r#"No key "{}" found in store "{store}""#, self.key))?;
+        println!("{value}");
+        Ok(())
+    }
+}
+
+impl KeysCommand {
+    pub async fn run(&self, client: impl CloudClientInterface) -> Result<()> {
+        let store = self.target.store_name(&client).await?;
+        let keys = client
+            .list_all_key_value_keys(&store, self.prefix.as_deref())
+            .await
+            .with_context(|| format!("Error listing keys in store '{store}'"))?;
+        if keys.is_empty() {
+            println!("No keys found");
+            return Ok(());
+        }
+        for key in keys {
+            println!("{key}");
+        }
+        Ok(())
+    }
+}
+
+impl DeleteKeyCommand {
+    pub async fn run(&self, client: impl CloudClientInterface) -> Result<()> {
+        let store = self.target.store_name(&client).await?;
+        if !self.yes {
+            let prompt = format!(
+                r#"Delete {} from key value store "{store}"? The action is irreversible."#,
+                self.keys
+                    .iter()
+                    .map(|k| format!(r#""{k}""#))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            let confirmed = dialoguer::Confirm::new()
+                .with_prompt(prompt)
+                .default(false)
+                .interact_opt()?
+                .unwrap_or_default();
+            if !confirmed {
+                println!("Will not delete keys.");
+                return Ok(());
+            }
+        }
+        for key in &self.keys {
+            client
+                .delete_key_value_pair(store.clone(), key.clone())
+                .await
+                .with_context(|| format!("Error deleting key '{key}' from store '{store}'"))?;
+            println!(r#"Key "{key}" deleted"#);
+        }
+        Ok(())
+    }
+}
+
+impl ImportCommand {
+    pub async fn run(&self, client: impl CloudClientInterface) -> Result<()> {
+        if self.concurrency == 0 {
+            bail!("Concurrency must be at least 1");
+        }
+        let content = if self.file.as_os_str() == "-" {
+            std::io::read_to_string(std::io::stdin()).context("Could not read from stdin")?
+        } else {
+            std::fs::read_to_string(&self.file)
+                .with_context(|| format!("Could not read {}", self.file.display()))?
+        };
+        let format = self
+            .format
+            .unwrap_or_else(|| TransferFormat::from_path(&self.file));
+        let pairs = parse_pairs(&content, format)
+            .with_context(|| format!("Could not parse {}", self.file.display()))?;
+
+        let store = self.target.store_name(&client).await?;
+        let count = pairs.len();
+        import_pairs(&client, &store, pairs, self.concurrency).await?;
+        println!(r#"Imported {count} key value pairs into store "{store}""#);
+        Ok(())
+    }
+}
+
+impl ExportCommand {
+    pub async fn run(&self, client: impl CloudClientInterface) -> Result<()> {
+        if self.concurrency == 0 {
+            bail!("Concurrency must be at least 1");
+        }
+        let format = match (self.format, &self.output) {
+            (Some(format), _) => format,
+            (None, Some(output)) => TransferFormat::from_path(output),
+            (None, None) => TransferFormat::Json,
+        };
+        let store = self.target.store_name(&client).await?;
+        let keys = client
+            .list_all_key_value_keys(&store, self.prefix.as_deref())
+            .await
+            .with_context(|| format!("Error listing keys in store '{store}'"))?;
+        let pairs = export_pairs(&client, &store, keys, self.concurrency).await?;
+        let content = format_pairs(&pairs, format)?;
+
+        match &self.output {
+            Some(output) => {
+                std::fs::write(output, content)
+                    .with_context(|| format!("Could not write {}", output.display()))?;
+                eprintln!(
+                    r#"Exported {} key value pairs from store "{store}" to {}"#,
+                    pairs.len(),
+                    output.display()
+                );
+            }
+            None => print!("{content}"),
+        }
+        Ok(())
+    }
+}
+
 impl RenameCommand {
     pub async fn run(&self, client: impl CloudClientInterface) -> Result<()> {
         let list = client
@@ -272,9 +576,19 @@ fn to_resource_links(stores: Vec<KeyValueStoreItem>) -> Vec<ResourceLinks> {
 #[cfg(test)]
 mod key_value_tests {
     use super::*;
+    use cloud::models::KeyValueKeyPage;
     use cloud::MockCloudClientInterface;
     use cloud_openapi::models::KeyValueStoreItem;
 
+    #[test]
+    fn test_transfers_default_to_the_shared_concurrency_limit() -> Result<()> {
+        let import = ImportCommand::try_parse_from(["import", "--store", "kv1", "seed.json"])?;
+        assert_eq!(MAX_CONCURRENT_REQUESTS, import.concurrency);
+        let export = ExportCommand::try_parse_from(["export", "--store", "kv1"])?;
+        assert_eq!(MAX_CONCURRENT_REQUESTS, export.concurrency);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_create_if_store_already_exists_then_error() -> Result<()> {
         let command = CreateCommand {
@@ -351,4 +665,108 @@ mod key_value_tests {
 
         command.run(mock).await
     }
+
+    fn store_target(store: &str) -> StoreTarget {
+        StoreTarget {
+            store: Some(store.to_owned()),
+            label: None,
+            app: None,
+        }
+    }
+
+    #[test]
+    fn test_set_values_are_read_from_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("value.bin");
+        std::fs::write(&path, [0xff, 0x00, 0x01])?;
+        let file_value = format!("@{}", path.display());
+
+        assert_eq!("plain", read_value("plain", false)?);
+        assert_eq!("@literal", read_value("@@literal", false)?);
+        assert_eq!("cGxhaW4=", read_value("plain", true)?);
+        assert_eq!("/wAB", read_value(&file_value, true)?);
+        read_value(&file_value, false).expect_err("should not have accepted binary data");
+        read_value("@does-not-exist", false).expect_err("should not have read a missing file");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_if_key_does_not_exist_then_error() -> Result<()> {
+        let command = GetCommand {
+            target: store_target("kv1"),
+            key: "missing".to_string(),
+            common: Default::default(),
+        };
+
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_get_key_value_stores()
+            .returning(move |_| Ok(vec![KeyValueStoreItem::new("kv1".to_string(), vec![])]));
+        mock.expect_get_key_value_pair()
+            .withf(|store, key| store == "kv1" && key == "missing")
+            .returning(|_, _| Ok(None));
+
+        let result = command.run(mock).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            r#"No key "missing" found in store "kv1""#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_listed_across_pages() -> Result<()> {
+        let command = KeysCommand {
+            target: store_target("kv1"),
+            prefix: Some("user:".to_string()),
+            common: Default::default(),
+        };
+
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_get_key_value_stores()
+            .returning(move |_| Ok(vec![KeyValueStoreItem::new("kv1".to_string(), vec![])]));
+        mock.expect_list_key_value_keys()
+            .withf(|store, prefix, _, page| {
+                store == "kv1" && prefix.as_deref() == Some("user:") && *page == Some(0)
+            })
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(KeyValueKeyPage {
+                    items: vec!["user:1".to_string()],
+                    is_last_page: false,
+                    ..Default::default()
+                })
+            });
+        mock.expect_list_key_value_keys()
+            .withf(|_, _, _, page| *page == Some(1))
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(KeyValueKeyPage {
+                    items: vec!["user:2".to_string()],
+                    is_last_page: true,
+                    ..Default::default()
+                })
+            });
+
+        command.run(mock).await
+    }
+
+    #[tokio::test]
+    async fn test_delete_key_deletes_each_key() -> Result<()> {
+        let command = DeleteKeyCommand {
+            target: store_target("kv1"),
+            keys: vec!["a".to_string(), "b".to_string()],
+            yes: true,
+            common: Default::default(),
+        };
+
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_get_key_value_stores()
+            .returning(move |_| Ok(vec![KeyValueStoreItem::new("kv1".to_string(), vec![])]));
+        mock.expect_delete_key_value_pair()
+            .withf(|store, key| store == "kv1" && (key == "a" || key == "b"))
+            .times(2)
+            .returning(|_, _| Ok(()));
+
+        command.run(mock).await
+    }
 }
@@ -0,0 +1,325 @@
+//! Bulk import and export of the pairs in a key value store. Values are
+//! text, so binary values are kept base64-encoded in the files.
+use std::cell::Cell;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use cloud::models::KeyValuePair;
+use cloud::CloudClientInterface;
+
+use crate::commands::parallel::{collect_all, run_all};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TransferFormat {
+    /// A JSON object of keys to values
+    Json,
+    /// One JSON object with a key and a value per line
+    Ndjson,
+    /// KEY=value lines, as in a .env file
+    Env,
+}
+
+impl TransferFormat {
+    /// Guesses the format of a file from its name, defaulting to JSON
+    pub(super) fn from_path(path: &Path) -> Self {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".ndjson") || name.ends_with(".jsonl") {
+            Self::Ndjson
+        } else if name == ".env" || name.starts_with(".env.") || name.ends_with(".env") {
+            Self::Env
+        } else {
+            Self::Json
+        }
+    }
+}
+
+pub(super) fn parse_pairs(content: &str, format: TransferFormat) -> Result<Vec<(String, String)>> {
+    match format {
+        TransferFormat::Json => {
+            let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(content)
+                .context("Expected a JSON object of keys to values")?;
+            object
+                .into_iter()
+                .map(|(key, value)| match value {
+                    serde_json::Value::String(value) => Ok((key, value)),
+                    _ => bail!("The value of key '{key}' is not a string"),
+                })
+                .collect()
+        }
+        TransferFormat::Ndjson => content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                let pair: KeyValuePair = serde_json::from_str(line).with_context(|| {
+                    format!(
+                        r#"Line {} is not an object with a "key" and a "value""#,
+                        index + 1
+                    )
+                })?;
+                Ok((pair.key, pair.value))
+            })
+            .collect(),
+        TransferFormat::Env => dotenvy::from_read_iter(content.as_bytes())
+            .map(|pair| pair.context("Invalid .env file"))
+            .collect(),
+    }
+}
+
+pub(super) fn format_pairs(pairs: &[(String, String)], format: TransferFormat) -> Result<String> {
+    let mut output = String::new();
+    match format {
+        TransferFormat::Json => {
+            let object = pairs
+                .iter()
+                .map(|(key, value)| (key.clone(), serde_json::Value::String(value.clone())))
+                .collect::<serde_json::Map<_, _>>();
+            output = serde_json::to_string_pretty(&object)?;
+            output.push('\n');
+        }
+        TransferFormat::Ndjson => {
+            for (key, value) in pairs {
+                let pair = KeyValuePair {
+                    key: key.clone(),
+                    value: value.clone(),
+                };
+                output.push_str(&serde_json::to_string(&pair)?);
+                output.push('\n');
+            }
+        }
+        TransferFormat::Env => {
+            for (key, value) in pairs {
+                if !is_env_key(key) {
+                    bail!("Key '{key}' cannot be written to a .env file. Use the JSON or NDJSON format instead.");
+                }
+                output.push_str(&format!("{key}=\"{}\"\n", escape_env_value(value)));
+            }
+        }
+    }
+    Ok(output)
+}
+
+fn is_env_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// Escapes a value to be double quoted in a .env file, without any of it being
+// taken as a variable to substitute
+fn escape_env_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | '"' | '$' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Sets pairs in a store, with at most `concurrency` requests at once. Every
+/// pair is tried, and all that could not be set are reported.
+pub(super) async fn import_pairs(
+    client: &impl CloudClientInterface,
+    store: &str,
+    pairs: Vec<(String, String)>,
+    concurrency: usize,
+) -> Result<()> {
+    let progress = Progress::new("Imported", pairs.len());
+    let tasks = pairs.into_iter().map(|(key, value)| {
+        let progress = &progress;
+        async move {
+            client
+                .add_key_value_pair(None, store.to_owned(), key.clone(), value)
+                .await
+                .with_context(|| format!("Error setting key '{key}' in store '{store}'"))?;
+            progress.advance();
+            Ok(())
+        }
+    });
+    let result = run_all(tasks, concurrency).await;
+    progress.finish();
+    result
+}
+
+/// Gets the values of keys in a store, with at most `concurrency` requests at
+/// once. Keys deleted since they were listed are skipped, and all keys that
+/// could not be read are reported.
+pub(super) async fn export_pairs(
+    client: &impl CloudClientInterface,
+    store: &str,
+    keys: Vec<String>,
+    concurrency: usize,
+) -> Result<Vec<(String, String)>> {
+    let progress = Progress::new("Exported", keys.len());
+    let tasks = keys.into_iter().map(|key| {
+        let progress = &progress;
+        async move {
+            let value = client
+                .get_key_value_pair(store.to_owned(), key.clone())
+                .await
+                .with_context(|| format!("Error getting key '{key}' from store '{store}'"))?;
+            progress.advance();
+            Ok(value.map(|value| (key, value)))
+        }
+    });
+    let pairs = collect_all(tasks, concurrency).await;
+    progress.finish();
+    Ok(pairs?.into_iter().flatten().collect())
+}
+
+/// Reports on stderr how many keys have been transferred, if it is a terminal
+struct Progress {
+    action: &'static str,
+    total: usize,
+    done: Cell<usize>,
+    visible: bool,
+}
+
+impl Progress {
+    fn new(action: &'static str, total: usize) -> Self {
+        Self {
+            action,
+            total,
+            done: Cell::new(0),
+            visible: std::io::stderr().is_terminal(),
+        }
+    }
+
+    fn advance(&self) {
+        self.done.set(self.done.get() + 1);
+        if self.visible {
+            eprint!("\r{} {}/{} keys", self.action, self.done.get(), self.total);
+        }
+    }
+
+    fn finish(&self) {
+        if self.visible && self.done.get() > 0 {
+            eprintln!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod transfer_tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_format_is_guessed_from_file_name() {
+        assert_eq!(
+            TransferFormat::Json,
+            TransferFormat::from_path(Path::new("seed.json"))
+        );
+        assert_eq!(
+            TransferFormat::Ndjson,
+            TransferFormat::from_path(Path::new("seed.jsonl"))
+        );
+        assert_eq!(
+            TransferFormat::Env,
+            TransferFormat::from_path(Path::new("dir/.env"))
+        );
+        assert_eq!(
+            TransferFormat::Env,
+            TransferFormat::from_path(Path::new("prod.env"))
+        );
+    }
+
+    #[test]
+    fn test_parse_pairs() {
+        assert_eq!(
+            pairs(&[("a", "1"), ("b", "2")]),
+            parse_pairs(r#"{"a": "1", "b": "2"}"#, TransferFormat::Json).unwrap()
+        );
+        parse_pairs(r#"{"a": 1}"#, TransferFormat::Json).expect_err("should reject numbers");
+        assert_eq!(
+            pairs(&[("a", "1"), ("b", "2")]),
+            parse_pairs(
+                "{\"key\": \"a\", \"value\": \"1\"}\n\n{\"key\": \"b\", \"value\": \"2\"}\n",
+                TransferFormat::Ndjson
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            pairs(&[("A", "1"), ("B", "two words")]),
+            parse_pairs("# comment\nA=1\nB='two words'\n", TransferFormat::Env).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_exported_pairs_can_be_imported() {
+        let exported = pairs(&[("A", "quoted \"$HOME\"\nand \\ more"), ("B", "")]);
+        for format in [
+            TransferFormat::Json,
+            TransferFormat::Ndjson,
+            TransferFormat::Env,
+        ] {
+            let content = format_pairs(&exported, format).unwrap();
+            assert_eq!(exported, parse_pairs(&content, format).unwrap());
+        }
+        format_pairs(&pairs(&[("user:1", "x")]), TransferFormat::Env)
+            .expect_err("should reject keys that are not valid in .env files");
+    }
+
+    #[tokio::test]
+    async fn test_import_sets_every_pair() {
+        let mut client = cloud::MockCloudClientInterface::new();
+        client
+            .expect_add_key_value_pair()
+            .withf(|_, store, _, _| store == "kv1")
+            .times(3)
+            .returning(|_, _, _, _| Ok(()));
+
+        import_pairs(
+            &client,
+            "kv1",
+            pairs(&[("a", "1"), ("b", "2"), ("c", "3")]),
+            2,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_every_failed_key_is_reported() {
+        let mut client = cloud::MockCloudClientInterface::new();
+        client
+            .expect_get_key_value_pair()
+            .times(4)
+            .returning(|_, key| match key.as_str() {
+                "a" | "c" => anyhow::bail!("unavailable"),
+                "d" => Ok(None),
+                _ => Ok(Some(format!("{key}-value"))),
+            });
+
+        let error = export_pairs(
+            &client,
+            "kv1",
+            vec!["a".into(), "b".into(), "c".into(), "d".into()],
+            2,
+        )
+        .await
+        .expect_err("export should have failed");
+        assert_eq!(
+            "2 operations failed:\n  Error getting key 'a' from store 'kv1': unavailable\n  Error getting key 'c' from store 'kv1': unavailable",
+            error.to_string()
+        );
+    }
+}
@@ -52,7 +52,7 @@ impl std::fmt::Display for ResourceGroupBy {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ResourceType {
     Database,
     KeyValueStore,
@@ -5,6 +5,7 @@ use anyhow::{bail, Context, Result};
 use clap::Parser;
 use cloud::{
     client::{Client, ConnectionConfig},
+    models::CloudCapabilities,
     CloudClientInterface,
 };
 use cloud_openapi::models::DeviceCodeItem;
@@ -119,6 +120,23 @@ pub struct LoginCommand {
         conflicts_with = "check-device-code"
     )]
     pub list: bool,
+
+    /// A trigger type that apps deployed with these login details may use.
+    /// If given, it replaces the trigger types the cloud instance advertises
+    /// as supported. Can be used multiple times.
+    #[clap(name = "trigger-type", long = "trigger-type")]
+    pub trigger_types: Vec<String>,
+
+    /// A host feature, such as a key value store, that apps deployed with
+    /// these login details may use. Requires --trigger-type, as the host
+    /// features replace those the cloud instance advertises along with the
+    /// trigger types. Can be used multiple times.
+    #[clap(
+        name = "host-feature",
+        long = "host-feature",
+        requires = "trigger-type"
+    )]
+    pub host_features: Vec<String>,
 }
 
 /// Log out of Fermyon Cloud.
@@ -270,6 +288,7 @@ impl LoginCommand {
             token,
             refresh_token: None,
             expiration: None,
+            capabilities: self.capabilities(),
         }
     }
 
@@ -280,9 +299,18 @@ impl LoginCommand {
             token: token_info.token,
             refresh_token: Some(token_info.refresh_token),
             expiration: Some(token_info.expiration),
+            capabilities: self.capabilities(),
         }
     }
 
+    // The capabilities given on the command line, if any
+    fn capabilities(&self) -> Option<CloudCapabilities> {
+        (!self.trigger_types.is_empty()).then(|| CloudCapabilities {
+            trigger_types: self.trigger_types.clone(),
+            host_features: self.host_features.clone(),
+        })
+    }
+
     fn config_file_path(&self) -> Result<PathBuf> {
         let root = config_root_dir()?;
 
@@ -321,7 +349,19 @@ impl LoginCommand {
 
     fn save_login_info(&self, login_connection: &LoginConnection) -> Result<(), anyhow::Error> {
         let path = self.config_file_path()?;
-        std::fs::write(path, serde_json::to_string_pretty(login_connection)?)?;
+        // Logging in again must not lose capabilities configured for the environment
+        let previous_capabilities = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|data| serde_json::from_str::<LoginConnection>(&data).ok())
+            .and_then(|previous| previous.capabilities);
+        let login_connection = LoginConnection {
+            capabilities: login_connection
+                .capabilities
+                .clone()
+                .or(previous_capabilities),
+            ..login_connection.clone()
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&login_connection)?)?;
         Ok(())
     }
 }
@@ -418,6 +458,14 @@ pub struct LoginConnection {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub expiration: Option<String>,
+    /// Overrides the trigger types and host features that the cloud
+    /// instance advertises. Set with the `--trigger-type` and
+    /// `--host-feature` options of `spin cloud login`, and kept when logging
+    /// in again without them. In the login file it is written as
+    /// `"capabilities": {"triggerTypes": ["http"], "hostFeatures": []}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub capabilities: Option<CloudCapabilities>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -506,3 +554,36 @@ fn parse_url_ensures_trailing_slash() {
     let url = parse_url("https://localhost:12345/foo/bar").unwrap();
     assert_eq!(url.to_string(), "https://localhost:12345/foo/bar/");
 }
+
+#[test]
+fn capabilities_are_set_from_login_options() {
+    let login = LoginCommand::try_parse_from(["login", "--token", "t"]).unwrap();
+    assert_eq!(
+        None,
+        login
+            .login_connection_for_token("t".to_owned())
+            .capabilities
+    );
+
+    let login = LoginCommand::try_parse_from([
+        "login",
+        "--trigger-type",
+        "http",
+        "--trigger-type",
+        "redis",
+        "--host-feature",
+        "key_value",
+    ])
+    .unwrap();
+    assert_eq!(
+        Some(CloudCapabilities {
+            trigger_types: vec!["http".to_owned(), "redis".to_owned()],
+            host_features: vec!["key_value".to_owned()],
+        }),
+        login
+            .login_connection_for_token("t".to_owned())
+            .capabilities
+    );
+    LoginCommand::try_parse_from(["login", "--host-feature", "key_value"])
+        .expect_err("--host-feature should require --trigger-type");
+}
@@ -1,17 +1,30 @@
+use std::io::{IsTerminal, Write};
 use std::ops::Sub;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use chrono::Utc;
-use cloud::{CloudClientExt, CloudClientInterface};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use cloud::{CloudClientExt, CloudClientInterface, DEFAULT_APPLIST_PAGE_SIZE};
 use cloud_openapi::models::Entry;
+use serde::Serialize;
 use std::option::Option;
 
 use crate::commands::create_cloud_client;
 use crate::opts::*;
 use clap::Parser;
+use regex::Regex;
 use uuid::Uuid;
 
+use self::dedup::SeenLines;
+use self::export::{parse_size, LogExport, DEFAULT_MAX_FILE_SIZE};
+use self::filter::{LogFilter, LogLevel};
+
+mod dedup;
+mod export;
+mod filter;
+
 /// fetch logs for an app from Fermyon Cloud
 #[derive(Parser, Debug)]
 pub struct LogsCommand {
@@ -25,8 +38,14 @@ pub struct LogsCommand {
     )]
     pub deployment_env_id: Option<String>,
 
-    /// App name
-    pub app: String,
+    /// Names of the apps to show logs for. Lines from several apps are
+    /// interleaved by time and prefixed with the app name.
+    #[clap(name = "app", required_unless_present = "all", conflicts_with = "all")]
+    pub apps: Vec<String>,
+
+    /// Show logs for all apps
+    #[clap(name = "all", long = "all")]
+    pub all: bool,
 
     /// Follow logs output
     #[clap(name = "follow", long = "follow")]
@@ -40,11 +59,18 @@ pub struct LogsCommand {
     #[clap(parse(try_from_str = parse_interval), name="interval", long="interval", default_value = "2")]
     pub interval_secs: std::time::Duration,
 
-    /// Only return logs newer than a relative duration. The duration format is a number
-    /// and a unit, where the unit is 's' for seconds, 'm' for minutes, 'h' for hours
-    /// or 'd' for days (e.g. "30m" for 30 minutes ago).  The default is 7 days.
-    #[clap(parse(try_from_str = parse_duration), name="since", long="since", default_value = "7d")]
-    pub since: std::time::Duration,
+    /// Only return logs newer than a relative duration or an RFC 3339 timestamp.
+    /// The duration format is a number and a unit, where the unit is 's' for seconds,
+    /// 'm' for minutes, 'h' for hours or 'd' for days (e.g. "30m" for 30 minutes ago).
+    /// The default is 7 days.
+    #[clap(parse(try_from_str = parse_time), name="since", long="since", default_value = "7d")]
+    pub since: DateTime<Utc>,
+
+    /// Only return logs older than a relative duration or an RFC 3339 timestamp,
+    /// in the same format as `--since`. When following logs, stop once this time
+    /// has passed.
+    #[clap(parse(try_from_str = parse_time), name = "until", long = "until")]
+    pub until: Option<DateTime<Utc>>,
 
     /// Show timestamps
     #[clap(
@@ -54,6 +80,91 @@ pub struct LogsCommand {
         action = clap::ArgAction::Set
     )]
     pub show_timestamp: bool,
+
+    /// Desired output format. `json` prints all log lines as a single array
+    /// of objects, and cannot be used with `--follow` or `--output`. `ndjson`
+    /// prints one object per log line, each on a line of its own.
+    #[clap(value_enum, name = "format", long = "format", default_value = "plain")]
+    pub format: LogFormat,
+
+    /// Only show log lines that match a regular expression. Matches are
+    /// highlighted when printing plain logs to a terminal.
+    #[clap(parse(try_from_str = Regex::new), name = "filter", long = "filter")]
+    pub filter: Option<Regex>,
+
+    /// Hide log lines that match a regular expression
+    #[clap(parse(try_from_str = Regex::new), name = "exclude", long = "exclude")]
+    pub exclude: Option<Regex>,
+
+    /// Only show log lines of at least this level. Levels are guessed from
+    /// keywords such as "ERROR" or "warn" in the line; lines without one are
+    /// considered to be at the info level.
+    #[clap(value_enum, name = "level", long = "level")]
+    pub level: Option<LogLevel>,
+
+    /// Write logs to files in this directory instead of printing them, one
+    /// file per app. Each app's progress is recorded in a checkpoint file, so
+    /// that running the command again resumes where it left off instead of
+    /// starting from `--since`. All lines are written, regardless of `--tail`.
+    #[clap(name = "output", long = "output")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Size at which log files written with `--output` are rotated, in bytes
+    /// or with a 'K', 'M' or 'G' unit. The default is 10M.
+    #[clap(parse(try_from_str = parse_size), name = "max-file-size", long = "max-file-size", requires = "output")]
+    pub max_file_size: Option<u64>,
+
+    /// Age at which log files written with `--output` are rotated, in the
+    /// same duration format as `--since`
+    #[clap(parse(try_from_str = parse_duration), name = "rotate-every", long = "rotate-every", requires = "output")]
+    pub rotate_every: Option<Duration>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Plain,
+    Json,
+    Ndjson,
+}
+
+// Colours cycled through to tell apart the lines of different apps
+const APP_COLOURS: &[&str] = &[
+    "\x1b[36m", "\x1b[33m", "\x1b[32m", "\x1b[35m", "\x1b[34m", "\x1b[31m",
+];
+const COLOUR_END: &str = "\x1b[0m";
+
+// The longest wait between polls while the cloud cannot be reached
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How fetched log lines are selected and printed
+struct LogOutput {
+    format: LogFormat,
+    show_timestamp: bool,
+    until: Option<DateTime<Utc>>,
+    filter: LogFilter,
+    /// Whether lines are prefixed with the name of their app, and to what
+    /// width the names are padded
+    prefix_width: Option<usize>,
+    colour: bool,
+}
+
+/// An app whose logs are shown, the time of the latest line seen and the
+/// lines that the next poll may return again
+struct AppLogs {
+    name: String,
+    id: Uuid,
+    since: String,
+    seen: SeenLines,
+    colour: &'static str,
+}
+
+/// A log line selected for printing
+struct FetchedLine<'a> {
+    /// The position of the line's app among the apps shown
+    app_index: usize,
+    entry: &'a Entry,
+    time: &'a str,
+    line: &'a str,
 }
 
 impl LogsCommand {
@@ -63,20 +174,88 @@ impl LogsCommand {
     }
 
     async fn logs(self, client: &impl CloudClientInterface) -> Result<()> {
-        let app_id = client
-            .get_app_id(&self.app)
-            .await
-            .with_context(|| format!("failed to find app with name {:?}", &self.app))?
-            .with_context(|| format!("app with name {:?} not found", &self.app))?;
+        let apps = if self.all {
+            all_apps(client).await?
+        } else {
+            let mut apps = vec![];
+            for name in &self.apps {
+                let app_id = client
+                    .get_app_id(name)
+                    .await
+                    .with_context(|| format!("failed to find app with name {:?}", name))?
+                    .with_context(|| format!("app with name {:?} not found", name))?;
+                apps.push((name.clone(), app_id));
+            }
+            apps
+        };
+        if apps.is_empty() {
+            bail!("No applications found");
+        }
+        if self.format == LogFormat::Json && (self.follow || self.output_dir.is_some()) {
+            bail!("`--format json` prints the logs as a single array, so it cannot be used with --follow or --output. Use `--format ndjson` instead.");
+        }
+        let since = self.since.to_rfc3339();
+        let mut apps = apps
+            .into_iter()
+            .zip(APP_COLOURS.iter().cycle())
+            .map(|((name, id), colour)| AppLogs {
+                name,
+                id,
+                since: since.clone(),
+                seen: SeenLines::default(),
+                colour,
+            })
+            .collect::<Vec<_>>();
+
+        // Colours are only for the terminal, never for exported files
+        let colour = self.format == LogFormat::Plain
+            && self.output_dir.is_none()
+            && std::io::stdout().is_terminal()
+            && std::env::var_os("NO_COLOR").is_none();
+        let prefix_width = match apps.len() {
+            1 => None,
+            _ => apps.iter().map(|a| a.name.len()).max(),
+        };
+        let mut export = match &self.output_dir {
+            Some(dir) => {
+                let export = LogExport::new(
+                    dir.clone(),
+                    self.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE),
+                    self.rotate_every,
+                )?;
+                for app in &mut apps {
+                    if let Some(since) = export.checkpoint(&app.name)? {
+                        // The lines up to the checkpoint were exported already
+                        app.seen = SeenLines::after(&since);
+                        app.since = since;
+                    }
+                }
+                Some(export)
+            }
+            None => None,
+        };
+        // Exports keep everything since the checkpoint or `--since`
+        let max_lines = match export {
+            Some(_) => None,
+            None => Some(self.max_lines),
+        };
 
+        let output = LogOutput {
+            format: self.format,
+            show_timestamp: self.show_timestamp,
+            until: self.until,
+            filter: LogFilter::new(self.filter, self.exclude, self.level, colour),
+            prefix_width,
+            colour,
+        };
         fetch_logs_and_print_loop(
             client,
-            app_id,
+            &mut apps,
             self.follow,
             self.interval_secs,
-            self.max_lines,
-            self.since,
-            self.show_timestamp,
+            max_lines,
+            &output,
+            export.as_mut(),
         )
         .await?;
 
@@ -84,56 +263,149 @@ impl LogsCommand {
     }
 }
 
+async fn all_apps(client: &impl CloudClientInterface) -> Result<Vec<(String, Uuid)>> {
+    let mut apps = vec![];
+    let mut page_index = 0;
+    loop {
+        let page = client
+            .list_apps(DEFAULT_APPLIST_PAGE_SIZE, Some(page_index))
+            .await
+            .context("failed to list apps")?;
+        apps.extend(page.items.into_iter().map(|app| (app.name, app.id)));
+        if page.is_last_page {
+            return Ok(apps);
+        }
+        page_index += 1;
+    }
+}
+
 async fn fetch_logs_and_print_loop(
     client: &impl CloudClientInterface,
-    app_id: Uuid,
+    apps: &mut [AppLogs],
     follow: bool,
     interval: Duration,
-    max_lines: i32,
-    since: Duration,
-    show_timestamp: bool,
+    max_lines: Option<i32>,
+    output: &LogOutput,
+    mut export: Option<&mut LogExport>,
 ) -> Result<()> {
-    let mut curr_since = Utc::now().sub(since).to_rfc3339();
-    curr_since =
-        fetch_logs_and_print_once(client, app_id, Some(max_lines), curr_since, show_timestamp)
-            .await?;
+    fetch_logs_and_print_once(client, apps, max_lines, output, export.as_deref_mut()).await?;
 
     if !follow {
         return Ok(());
     }
 
+    // Failed polls are retried with exponential backoff rather than ending
+    // the session, since the cloud may only be unreachable for a moment
+    let mut failures = 0;
     loop {
-        tokio::time::sleep(interval).await;
-        curr_since =
-            fetch_logs_and_print_once(client, app_id, None, curr_since, show_timestamp).await?;
+        if output.until.is_some_and(|until| Utc::now() > until) {
+            return Ok(());
+        }
+        tokio::time::sleep(retry_interval(interval, failures)).await;
+        match fetch_logs_and_print_once(client, apps, None, output, export.as_deref_mut()).await {
+            Ok(()) => {
+                if failures > 0 {
+                    eprintln!("Reconnected to Fermyon Cloud");
+                }
+                failures = 0;
+            }
+            Err(e) => {
+                failures += 1;
+                eprintln!(
+                    "Failed to fetch logs: {e:#}. Retrying in {} seconds...",
+                    retry_interval(interval, failures).as_secs()
+                );
+            }
+        }
     }
 }
 
+// The wait before the next poll, doubling with each consecutive failure
+fn retry_interval(interval: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return interval;
+    }
+    interval
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(MAX_RETRY_INTERVAL.max(interval))
+}
+
 async fn fetch_logs_and_print_once(
     client: &impl CloudClientInterface,
-    app_id: Uuid,
+    apps: &mut [AppLogs],
     max_lines: Option<i32>,
-    since: String,
-    show_timestamp: bool,
-) -> Result<String> {
-    let entries = client
-        .app_logs_raw(app_id.to_string(), max_lines, Some(since.to_string()))
-        .await?
-        .entries;
+    output: &LogOutput,
+    export: Option<&mut LogExport>,
+) -> Result<()> {
+    // Lines after `until` and lines not matching the filter are dropped
+    // locally, so the tail has to be taken locally too
+    let server_max_lines = if output.until.is_some() || output.filter.is_active() {
+        None
+    } else {
+        max_lines
+    };
+    let responses = futures::future::try_join_all(apps.iter().map(|app| {
+        client.app_logs_raw(
+            app.id.to_string(),
+            server_max_lines,
+            Some(app.seen.poll_since(&app.since)),
+        )
+    }))
+    .await?;
 
-    if entries.is_empty() {
-        return Ok(since.to_owned());
-    }
+    let selected = apps
+        .iter_mut()
+        .zip(&responses)
+        .enumerate()
+        .map(|(index, (app, response))| {
+            select_lines(index, &response.entries, max_lines, output, &mut app.seen)
+        })
+        .collect::<Vec<_>>();
 
-    let updated_since = print_logs(&entries, show_timestamp);
-    if let Some(u) = updated_since {
-        return Ok(u.to_owned());
+    match export {
+        Some(export) => {
+            for (app, (app_lines, since)) in apps.iter().zip(&selected) {
+                let formatted = app_lines
+                    .iter()
+                    .map(|l| format_line(l, app, output, false))
+                    .collect::<Result<Vec<_>>>()?;
+                export.write(&app.name, &formatted, *since)?;
+            }
+        }
+        None => {
+            let mut lines = selected
+                .iter()
+                .flat_map(|(app_lines, _)| app_lines.iter())
+                .collect::<Vec<_>>();
+            if apps.len() > 1 {
+                interleave(&mut lines);
+            }
+            print_logs(apps, &lines, output)?;
+        }
     }
+    let updated_since = selected
+        .iter()
+        .map(|(_, since)| since.map(str::to_owned))
+        .collect::<Vec<_>>();
 
-    Ok(since)
+    for (app, since) in apps.iter_mut().zip(updated_since) {
+        if let Some(since) = since {
+            app.since = since;
+        }
+    }
+    Ok(())
 }
 
-fn print_logs(entries: &[Entry], show_timestamp: bool) -> Option<&str> {
+// Selects the lines of an app's entries to print, skipping those already
+// seen, and returns them along with the time of the latest line seen
+fn select_lines<'a>(
+    app_index: usize,
+    entries: &'a [Entry],
+    max_lines: Option<i32>,
+    output: &LogOutput,
+    seen: &mut SeenLines,
+) -> (Vec<FetchedLine<'a>>, Option<&'a str>) {
+    let mut lines = vec![];
     let mut since = None;
     for entry in entries.iter().rev() {
         let Some(log_lines) = entry.log_lines.as_ref() else {
@@ -146,17 +418,140 @@ fn print_logs(entries: &[Entry], show_timestamp: bool) -> Option<&str> {
             };
 
             if let Some(time) = &log_entry.time {
-                if show_timestamp {
-                    println!("[{time}] {log}");
-                } else {
-                    println!("{log}");
+                if is_after(time, output.until) {
+                    continue;
                 }
+                // Filtered out lines still move on where the next poll starts
                 since = Some(time.as_str());
+                if output.filter.matches(log) {
+                    lines.push(FetchedLine {
+                        app_index,
+                        entry,
+                        time,
+                        line: log,
+                    });
+                }
             }
         }
     }
 
-    since
+    seen.retain_new(&mut lines);
+    if let Some(max_lines) = max_lines {
+        let max_lines = usize::try_from(max_lines).unwrap_or_default();
+        lines.drain(..lines.len().saturating_sub(max_lines));
+    }
+
+    (lines, since)
+}
+
+// Orders the lines of several apps by time. The sort is stable, so lines with
+// the same time keep their order.
+fn interleave(lines: &mut [&FetchedLine<'_>]) {
+    lines.sort_by_key(|l| DateTime::parse_from_rfc3339(l.time).ok());
+}
+
+fn print_logs(apps: &[AppLogs], lines: &[&FetchedLine<'_>], output: &LogOutput) -> Result<()> {
+    // Logs are only fetched once in this format, so they form a single array
+    if output.format == LogFormat::Json {
+        println!("{}", json_array(apps, lines)?);
+        return Ok(());
+    }
+    for line in lines {
+        let app = &apps[line.app_index];
+        println!("{}", format_line(line, app, output, true)?);
+    }
+    // Show each poll's lines as soon as they arrive, even when piped
+    std::io::stdout().flush()?;
+
+    Ok(())
+}
+
+fn json_array(apps: &[AppLogs], lines: &[&FetchedLine<'_>]) -> Result<String> {
+    let lines = lines
+        .iter()
+        .map(|l| LogLine::new(&apps[l.app_index].name, l.entry, l.time, l.line))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(serde_json::to_string_pretty(&lines)?)
+}
+
+// Formats a line in the output format. Plain lines are prefixed with their
+// app name if `prefixed` is set and lines from several apps are shown.
+fn format_line(
+    fetched: &FetchedLine<'_>,
+    app: &AppLogs,
+    output: &LogOutput,
+    prefixed: bool,
+) -> Result<String> {
+    let FetchedLine {
+        entry, time, line, ..
+    } = fetched;
+    let formatted = match output.format {
+        LogFormat::Plain => {
+            let prefix = match output.prefix_width {
+                Some(width) if prefixed && output.colour => {
+                    format!("{}{:width$}{COLOUR_END} | ", app.colour, app.name)
+                }
+                Some(width) if prefixed => format!("{:width$} | ", app.name),
+                _ => String::new(),
+            };
+            let line = output.filter.highlight(line);
+            if output.show_timestamp {
+                format!("{prefix}[{time}] {line}")
+            } else {
+                format!("{prefix}{line}")
+            }
+        }
+        LogFormat::Json | LogFormat::Ndjson => {
+            serde_json::to_string(&LogLine::new(&app.name, entry, time, line)?)?
+        }
+    };
+    Ok(formatted)
+}
+
+fn is_after(time: &str, until: Option<DateTime<Utc>>) -> bool {
+    match (until, DateTime::parse_from_rfc3339(time)) {
+        (Some(until), Ok(time)) => time > until,
+        _ => false,
+    }
+}
+
+/// A log line in the structured output formats
+#[derive(Serialize)]
+struct LogLine<'a> {
+    timestamp: &'a str,
+    app: &'a str,
+    line: &'a str,
+    /// Any other information, such as the source, that the cloud provides
+    /// about the entry the line belongs to
+    #[serde(flatten)]
+    metadata: serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'a> LogLine<'a> {
+    fn new(app: &'a str, entry: &Entry, timestamp: &'a str, line: &'a str) -> Result<Self> {
+        let mut metadata = match serde_json::to_value(entry)? {
+            serde_json::Value::Object(metadata) => metadata,
+            _ => Default::default(),
+        };
+        metadata.retain(|k, v| !matches!(k.as_str(), "logLines" | "log_lines") && !v.is_null());
+        Ok(Self {
+            timestamp,
+            app,
+            line,
+            metadata,
+        })
+    }
+}
+
+// Parses either an RFC 3339 timestamp or a duration relative to now
+fn parse_time(arg: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(arg) {
+        return Ok(time.to_utc());
+    }
+    let duration = parse_duration(arg).with_context(|| {
+        format!("'{arg}' is neither an RFC 3339 timestamp nor a relative duration")
+    })?;
+    Ok(Utc::now().sub(duration))
 }
 
 fn parse_duration(arg: &str) -> anyhow::Result<std::time::Duration> {
@@ -173,7 +568,9 @@ fn parse_duration(arg: &str) -> anyhow::Result<std::time::Duration> {
         let value: u64 = parg.parse()?;
         std::time::Duration::from_secs(value * 24 * 60 * 60)
     } else {
-        bail!(r#"since must be a number followed by an allowed unit ("300s", "5m", "4h" or "1d")"#);
+        bail!(
+            r#"duration must be a number followed by an allowed unit ("300s", "5m", "4h" or "1d")"#
+        );
     };
 
     Ok(duration)
@@ -187,3 +584,156 @@ fn parse_interval(arg: &str) -> anyhow::Result<std::time::Duration> {
 
     Ok(std::time::Duration::from_secs(value))
 }
+
+#[cfg(test)]
+mod logs_tests {
+    use super::*;
+    use cloud_openapi::models::LogEntry;
+
+    #[test]
+    fn test_parse_duration_error_does_not_name_an_option() {
+        assert_eq!(
+            Duration::from_secs(2 * 60 * 60),
+            parse_duration("2h").unwrap()
+        );
+        let error = parse_duration("5x").unwrap_err().to_string();
+        assert!(error.starts_with("duration must be"), "{error}");
+    }
+
+    #[test]
+    fn test_parse_time_accepts_timestamps_and_durations() {
+        let time = parse_time("2024-01-02T03:04:05+01:00").unwrap();
+        assert_eq!("2024-01-02T02:04:05+00:00", time.to_rfc3339());
+
+        let time = parse_time("30m").unwrap();
+        let expected = Utc::now().sub(Duration::from_secs(30 * 60));
+        assert!((expected - time).num_seconds().abs() < 5);
+
+        parse_time("yesterday").expect_err("should not have accepted 'yesterday'");
+    }
+
+    #[test]
+    fn test_lines_after_until_are_dropped() {
+        let until = parse_time("2024-01-02T00:00:00Z").unwrap();
+        assert!(!is_after("2024-01-01T23:59:59Z", Some(until)));
+        assert!(is_after("2024-01-02T00:00:01Z", Some(until)));
+        assert!(!is_after("2024-01-02T00:00:01Z", None));
+    }
+
+    fn app_logs(name: &str) -> AppLogs {
+        AppLogs {
+            name: name.to_owned(),
+            id: Uuid::new_v4(),
+            since: String::new(),
+            seen: SeenLines::default(),
+            colour: APP_COLOURS[0],
+        }
+    }
+
+    fn entry(lines: &[(&str, &str)]) -> Entry {
+        Entry {
+            log_lines: Some(
+                lines
+                    .iter()
+                    .map(|(time, line)| LogEntry {
+                        time: Some(time.to_string()),
+                        line: Some(line.to_string()),
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_lines_of_several_apps_are_interleaved_by_time() {
+        let output = LogOutput {
+            format: LogFormat::Plain,
+            show_timestamp: true,
+            until: None,
+            filter: LogFilter::default(),
+            prefix_width: Some(5),
+            colour: false,
+        };
+        let mut apps = [app_logs("app-a"), app_logs("app-b")];
+        let entries_a = [entry(&[
+            ("2024-01-01T00:00:01Z", "a1"),
+            ("2024-01-01T00:00:03Z", "a2"),
+        ])];
+        let entries_b = [entry(&[
+            ("2024-01-01T00:00:02Z", "b1"),
+            ("2024-01-01T00:00:04Z", "b2"),
+        ])];
+
+        let (lines_a, since_a) = select_lines(0, &entries_a, Some(10), &output, &mut apps[0].seen);
+        let (lines_b, since_b) = select_lines(1, &entries_b, Some(10), &output, &mut apps[1].seen);
+        let mut lines = lines_a.iter().chain(&lines_b).collect::<Vec<_>>();
+        interleave(&mut lines);
+
+        let printed = lines
+            .iter()
+            .map(|l| format!("{}:{}", apps[l.app_index].name, l.line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["app-a:a1", "app-b:b1", "app-a:a2", "app-b:b2"],
+            printed
+        );
+        assert_eq!(Some("2024-01-01T00:00:03Z"), since_a);
+        assert_eq!(Some("2024-01-01T00:00:04Z"), since_b);
+    }
+
+    #[test]
+    fn test_retry_interval_backs_off_exponentially() {
+        let interval = Duration::from_secs(2);
+        assert_eq!(interval, retry_interval(interval, 0));
+        assert_eq!(Duration::from_secs(4), retry_interval(interval, 1));
+        assert_eq!(Duration::from_secs(16), retry_interval(interval, 3));
+        assert_eq!(MAX_RETRY_INTERVAL, retry_interval(interval, 40));
+    }
+
+    #[test]
+    fn test_structured_log_line_includes_entry_metadata() {
+        let entry: Entry = serde_json::from_value(serde_json::json!({
+            "source": "my-component",
+        }))
+        .unwrap();
+        let line = LogLine::new("my-app", &entry, "2024-01-02T00:00:00Z", "hello").unwrap();
+        let json = serde_json::to_value(&line).unwrap();
+        assert_eq!("my-app", json["app"]);
+        assert_eq!("hello", json["line"]);
+        assert_eq!("2024-01-02T00:00:00Z", json["timestamp"]);
+        assert_eq!("my-component", json["source"]);
+    }
+
+    #[test]
+    fn test_structured_formats_are_valid_json() {
+        let apps = [app_logs("a")];
+        let entry = entry(&[("2024-01-01T00:00:01Z", "one\ntwo")]);
+        let line = FetchedLine {
+            app_index: 0,
+            entry: &entry,
+            time: "2024-01-01T00:00:01Z",
+            line: "one\ntwo",
+        };
+        let output = LogOutput {
+            format: LogFormat::Ndjson,
+            show_timestamp: false,
+            until: None,
+            filter: LogFilter::default(),
+            prefix_width: None,
+            colour: false,
+        };
+
+        let ndjson = format_line(&line, &apps[0], &output, false).unwrap();
+        assert!(!ndjson.contains('\n'), "{ndjson}");
+        let object: serde_json::Value = serde_json::from_str(&ndjson).unwrap();
+        assert_eq!("one\ntwo", object["line"]);
+
+        let array: serde_json::Value =
+            serde_json::from_str(&json_array(&apps, &[&line, &line]).unwrap()).unwrap();
+        assert_eq!(2, array.as_array().unwrap().len());
+        let empty: serde_json::Value =
+            serde_json::from_str(&json_array(&apps, &[]).unwrap()).unwrap();
+        assert_eq!(serde_json::json!([]), empty);
+    }
+}
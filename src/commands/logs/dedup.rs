@@ -0,0 +1,175 @@
+//! Deduplication of log lines across polls. Each poll starts a little before
+//! the latest line seen so far, so that lines sharing its timestamp or
+//! arriving late are not missed, and lines that an earlier poll already
+//! returned are skipped.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use chrono::{DateTime, FixedOffset, TimeDelta};
+
+use super::FetchedLine;
+
+/// How far before the latest line seen each poll starts
+const POLL_OVERLAP: TimeDelta = TimeDelta::seconds(1);
+
+/// The lines of an app seen within the overlap of the next poll
+#[derive(Default)]
+pub(super) struct SeenLines {
+    /// How often each line was returned by a single poll and when it was
+    /// logged, by hash of its time and content
+    lines: HashMap<u64, (usize, Option<DateTime<FixedOffset>>)>,
+    /// The time up to which all lines have been seen, such as by an earlier
+    /// run of the command
+    after: Option<DateTime<FixedOffset>>,
+}
+
+impl SeenLines {
+    /// Starts with every line logged up to and including the given time
+    /// taken as seen
+    pub(super) fn after(time: &str) -> Self {
+        Self {
+            after: DateTime::parse_from_rfc3339(time).ok(),
+            ..Default::default()
+        }
+    }
+
+    /// The time to start the next poll from, given the time of the latest
+    /// line seen. The first poll starts from the given time as is.
+    pub(super) fn poll_since(&self, since: &str) -> String {
+        if self.lines.is_empty() {
+            return since.to_owned();
+        }
+        match DateTime::parse_from_rfc3339(since) {
+            Ok(time) => (time - POLL_OVERLAP).to_rfc3339(),
+            Err(_) => since.to_owned(),
+        }
+    }
+
+    /// Drops the lines that earlier polls already returned. A line that is
+    /// logged several times at the same time is kept as often as it occurs
+    /// beyond what earlier polls returned.
+    pub(super) fn retain_new(&mut self, lines: &mut Vec<FetchedLine<'_>>) {
+        let mut polled = HashMap::<u64, (usize, Option<DateTime<FixedOffset>>)>::new();
+        if let Some(after) = self.after {
+            lines
+                .retain(|l| DateTime::parse_from_rfc3339(l.time).map_or(true, |time| time > after));
+        }
+        lines.retain(|l| {
+            let hash = hash_line(l.time, l.line);
+            let (count, _) = polled
+                .entry(hash)
+                .or_insert_with(|| (0, DateTime::parse_from_rfc3339(l.time).ok()));
+            *count += 1;
+            let seen = self.lines.get(&hash).map_or(0, |(seen, _)| *seen);
+            *count > seen
+        });
+
+        for (hash, (count, time)) in polled {
+            let seen = self.lines.entry(hash).or_insert((0, time));
+            seen.0 = seen.0.max(count);
+        }
+        // Lines before the start of the next poll cannot be returned again
+        if let Some(latest) = self.lines.values().filter_map(|(_, time)| *time).max() {
+            self.lines
+                .retain(|_, (_, time)| time.is_some_and(|t| t >= latest - POLL_OVERLAP));
+        }
+    }
+}
+
+fn hash_line(time: &str, line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    time.hash(&mut hasher);
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod dedup_tests {
+    use super::*;
+    use cloud_openapi::models::Entry;
+
+    fn fetched<'a>(entry: &'a Entry, lines: &[(&'a str, &'a str)]) -> Vec<FetchedLine<'a>> {
+        lines
+            .iter()
+            .map(|(time, line)| FetchedLine {
+                app_index: 0,
+                entry,
+                time,
+                line,
+            })
+            .collect()
+    }
+
+    fn texts(lines: &[FetchedLine<'_>]) -> Vec<String> {
+        lines.iter().map(|l| l.line.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_lines_returned_by_overlapping_polls_are_skipped() {
+        let entry = Entry::default();
+        let mut seen = SeenLines::default();
+        assert_eq!(
+            "2024-01-01T00:00:00Z",
+            seen.poll_since("2024-01-01T00:00:00Z")
+        );
+
+        let mut first = fetched(
+            &entry,
+            &[
+                ("2024-01-01T00:00:01Z", "a"),
+                ("2024-01-01T00:00:02Z", "b"),
+                ("2024-01-01T00:00:02Z", "b"),
+            ],
+        );
+        seen.retain_new(&mut first);
+        assert_eq!(vec!["a", "b", "b"], texts(&first));
+        assert_eq!(
+            "2024-01-01T00:00:01+00:00",
+            seen.poll_since("2024-01-01T00:00:02Z")
+        );
+
+        let mut second = fetched(
+            &entry,
+            &[
+                ("2024-01-01T00:00:01Z", "a"),
+                ("2024-01-01T00:00:02Z", "b"),
+                ("2024-01-01T00:00:02Z", "b"),
+                ("2024-01-01T00:00:02Z", "b"),
+                ("2024-01-01T00:00:02Z", "c"),
+            ],
+        );
+        seen.retain_new(&mut second);
+        assert_eq!(vec!["b", "c"], texts(&second));
+    }
+
+    #[test]
+    fn test_lines_up_to_a_resumed_time_are_skipped() {
+        let entry = Entry::default();
+        let mut seen = SeenLines::after("2024-01-01T00:00:02Z");
+        assert_eq!(
+            "2024-01-01T00:00:02Z",
+            seen.poll_since("2024-01-01T00:00:02Z")
+        );
+
+        let mut lines = fetched(
+            &entry,
+            &[
+                ("2024-01-01T00:00:01Z", "a"),
+                ("2024-01-01T00:00:02Z", "b"),
+                ("2024-01-01T00:00:03Z", "c"),
+            ],
+        );
+        seen.retain_new(&mut lines);
+        assert_eq!(vec!["c"], texts(&lines));
+    }
+
+    #[test]
+    fn test_lines_before_the_overlap_are_forgotten() {
+        let entry = Entry::default();
+        let mut seen = SeenLines::default();
+        seen.retain_new(&mut fetched(&entry, &[("2024-01-01T00:00:00Z", "a")]));
+        seen.retain_new(&mut fetched(&entry, &[("2024-01-01T00:00:05Z", "b")]));
+        assert_eq!(1, seen.lines.len());
+    }
+}
@@ -0,0 +1,231 @@
+//! Export of app logs to rotating local files, with a checkpoint per app so
+//! that an interrupted export resumes where it left off
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+
+/// The size at which log files are rotated if no other size is given
+pub(super) const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+pub(super) struct LogExport {
+    dir: PathBuf,
+    max_file_size: u64,
+    rotate_every: Option<Duration>,
+    files: HashMap<String, LogFile>,
+}
+
+/// The file that an app's logs are currently written to
+struct LogFile {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl LogExport {
+    pub(super) fn new(
+        dir: PathBuf,
+        max_file_size: u64,
+        rotate_every: Option<Duration>,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Could not create directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            max_file_size,
+            rotate_every,
+            files: HashMap::new(),
+        })
+    }
+
+    /// The time of the last line exported for an app, if any
+    pub(super) fn checkpoint(&self, app: &str) -> Result<Option<String>> {
+        let path = self.checkpoint_path(app);
+        match std::fs::read_to_string(&path) {
+            Ok(since) => Ok(Some(since.trim().to_owned())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Could not read {}", path.display())),
+        }
+    }
+
+    /// Appends lines to an app's log file, rotating it as needed, and then
+    /// records the time of the last line seen as the app's checkpoint
+    pub(super) fn write(&mut self, app: &str, lines: &[String], since: Option<&str>) -> Result<()> {
+        for line in lines {
+            let len = line.len() as u64 + 1;
+            if self.needs_rotation(app, len) {
+                self.rotate(app)?;
+            }
+            let log_file = self.log_file(app)?;
+            writeln!(log_file.file, "{line}")?;
+            log_file.size += len;
+        }
+        if let Some(log_file) = self.files.get_mut(app) {
+            log_file.file.flush()?;
+        }
+
+        if let Some(since) = since {
+            // Write the checkpoint in one go, so that an interrupted export
+            // never leaves a partial checkpoint behind
+            let path = self.checkpoint_path(app);
+            let temp_path = path.with_extension("checkpoint.tmp");
+            std::fs::write(&temp_path, since)?;
+            std::fs::rename(&temp_path, &path)
+                .with_context(|| format!("Could not write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn needs_rotation(&self, app: &str, len: u64) -> bool {
+        let Some(log_file) = self.files.get(app) else {
+            return false;
+        };
+        let too_big = log_file.size > 0 && log_file.size + len > self.max_file_size;
+        let too_old = self
+            .rotate_every
+            .is_some_and(|every| log_file.opened.elapsed() >= every);
+        too_big || too_old
+    }
+
+    fn rotate(&mut self, app: &str) -> Result<()> {
+        self.files.remove(app);
+        let path = self.log_path(app);
+        // Files rotated at the same time are told apart by a counter
+        let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+        let rotated = (0..)
+            .map(|count| self.dir.join(format!("{app}.{timestamp}.{count}.log")))
+            .find(|rotated| !rotated.exists())
+            .unwrap();
+        std::fs::rename(&path, &rotated)
+            .with_context(|| format!("Could not rotate {}", path.display()))
+    }
+
+    fn log_file(&mut self, app: &str) -> Result<&mut LogFile> {
+        if !self.files.contains_key(app) {
+            let path = self.log_path(app);
+            let file = open_append(&path)?;
+            let size = file.metadata()?.len();
+            self.files.insert(
+                app.to_owned(),
+                LogFile {
+                    file,
+                    size,
+                    opened: Instant::now(),
+                },
+            );
+        }
+        Ok(self.files.get_mut(app).unwrap())
+    }
+
+    fn log_path(&self, app: &str) -> PathBuf {
+        self.dir.join(format!("{app}.log"))
+    }
+
+    fn checkpoint_path(&self, app: &str) -> PathBuf {
+        self.dir.join(format!("{app}.checkpoint"))
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Could not open {}", path.display()))
+}
+
+// Parses a size in bytes, optionally followed by a 'K', 'M' or 'G' unit
+pub(super) fn parse_size(arg: &str) -> Result<u64> {
+    let lower = arg.trim().to_ascii_lowercase();
+    let number = lower.trim_end_matches('b');
+    let (number, multiplier) = if let Some(n) = number.strip_suffix('k') {
+        (n, 1024)
+    } else if let Some(n) = number.strip_suffix('m') {
+        (n, 1024 * 1024)
+    } else if let Some(n) = number.strip_suffix('g') {
+        (n, 1024 * 1024 * 1024)
+    } else {
+        (number, 1)
+    };
+    let Ok(value) = number.parse::<u64>() else {
+        bail!(r#"size must be a number optionally followed by a unit ("500K", "10M" or "1G")"#);
+    };
+    if value == 0 {
+        bail!("size must be greater than zero");
+    }
+    Ok(value * multiplier)
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+
+    fn log_files(dir: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|n| n.ends_with(".log"))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(100, parse_size("100").unwrap());
+        assert_eq!(500 * 1024, parse_size("500K").unwrap());
+        assert_eq!(10 * 1024 * 1024, parse_size("10mb").unwrap());
+        parse_size("ten").expect_err("should not have accepted 'ten'");
+        parse_size("0").expect_err("should not have accepted '0'");
+    }
+
+    #[test]
+    fn test_files_are_rotated_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut export = LogExport::new(dir.path().to_owned(), 12, None).unwrap();
+
+        export
+            .write("app", &["12345".to_owned(), "6789".to_owned()], None)
+            .unwrap();
+        assert_eq!(vec!["app.log"], log_files(dir.path()));
+
+        export.write("app", &["abc".to_owned()], None).unwrap();
+        let files = log_files(dir.path());
+        assert_eq!(2, files.len());
+        assert_eq!(
+            "abc\n",
+            std::fs::read_to_string(dir.path().join("app.log")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_files_rotated_at_once_do_not_collide() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut export = LogExport::new(dir.path().to_owned(), 1, None).unwrap();
+
+        let lines = (0..20).map(|i| i.to_string()).collect::<Vec<_>>();
+        export.write("app", &lines, None).unwrap();
+        assert_eq!(20, log_files(dir.path()).len());
+    }
+
+    #[test]
+    fn test_checkpoint_is_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut export = LogExport::new(dir.path().to_owned(), 1024, None).unwrap();
+        assert_eq!(None, export.checkpoint("app").unwrap());
+
+        export
+            .write("app", &["line".to_owned()], Some("2024-01-01T00:00:00Z"))
+            .unwrap();
+
+        let export = LogExport::new(dir.path().to_owned(), 1024, None).unwrap();
+        assert_eq!(
+            Some("2024-01-01T00:00:00Z".to_owned()),
+            export.checkpoint("app").unwrap()
+        );
+    }
+}
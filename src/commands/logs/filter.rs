@@ -0,0 +1,142 @@
+//! Selection and highlighting of log lines by pattern and level
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+use regex::Regex;
+
+// Keywords that commonly mark the level of a log line, for apps that do not
+// log in a structured format
+lazy_static::lazy_static! {
+    static ref LEVEL_KEYWORD: Regex = Regex::new(
+        r"(?i)\b(fatal|panic|panicked|error|err|warning|warn|info|debug|trace)\b"
+    )
+    .expect("Invalid level regex");
+}
+
+const HIGHLIGHT_START: &str = "\x1b[1;31m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// Guesses the level of a line from the first level keyword in it. Lines
+    /// without one are considered informational.
+    fn detect(line: &str) -> Self {
+        let Some(keyword) = LEVEL_KEYWORD.find(line) else {
+            return Self::Info;
+        };
+        match keyword.as_str().to_ascii_lowercase().as_str() {
+            "trace" => Self::Trace,
+            "debug" => Self::Debug,
+            "info" => Self::Info,
+            "warn" | "warning" => Self::Warn,
+            _ => Self::Error,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(super) struct LogFilter {
+    filter: Option<Regex>,
+    exclude: Option<Regex>,
+    level: Option<LogLevel>,
+    highlight: bool,
+}
+
+impl LogFilter {
+    pub(super) fn new(
+        filter: Option<Regex>,
+        exclude: Option<Regex>,
+        level: Option<LogLevel>,
+        highlight: bool,
+    ) -> Self {
+        Self {
+            filter,
+            exclude,
+            level,
+            highlight,
+        }
+    }
+
+    /// Whether the filter can drop lines
+    pub(super) fn is_active(&self) -> bool {
+        self.filter.is_some() || self.exclude.is_some() || self.level.is_some()
+    }
+
+    pub(super) fn matches(&self, line: &str) -> bool {
+        if let Some(filter) = &self.filter {
+            if !filter.is_match(line) {
+                return false;
+            }
+        }
+        if let Some(exclude) = &self.exclude {
+            if exclude.is_match(line) {
+                return false;
+            }
+        }
+        match self.level {
+            Some(level) => LogLevel::detect(line) >= level,
+            None => true,
+        }
+    }
+
+    /// Marks the parts of the line that match `--filter` with terminal colours,
+    /// if highlighting is enabled
+    pub(super) fn highlight<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        match &self.filter {
+            Some(filter) if self.highlight => {
+                filter.replace_all(line, format!("{HIGHLIGHT_START}$0{HIGHLIGHT_END}"))
+            }
+            _ => Cow::Borrowed(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+
+    #[test]
+    fn test_level_is_detected_from_keywords() {
+        assert_eq!(LogLevel::Error, LogLevel::detect("ERROR failed to connect"));
+        assert_eq!(
+            LogLevel::Error,
+            LogLevel::detect("thread 'main' panicked at")
+        );
+        assert_eq!(LogLevel::Warn, LogLevel::detect("[warning] retrying"));
+        assert_eq!(LogLevel::Debug, LogLevel::detect("level=debug msg=hello"));
+        assert_eq!(LogLevel::Info, LogLevel::detect("request handled"));
+        assert_eq!(LogLevel::Info, LogLevel::detect("errors: 0, informal"));
+    }
+
+    #[test]
+    fn test_lines_must_match_filter_and_not_exclude_and_reach_level() {
+        let filter = LogFilter::new(
+            Some(Regex::new("GET").unwrap()),
+            Some(Regex::new("/health").unwrap()),
+            Some(LogLevel::Warn),
+            false,
+        );
+        assert!(filter.matches("WARN GET /api slow"));
+        assert!(!filter.matches("WARN GET /health slow"));
+        assert!(!filter.matches("WARN POST /api slow"));
+        assert!(!filter.matches("INFO GET /api"));
+        assert!(LogFilter::default().matches("anything"));
+    }
+
+    #[test]
+    fn test_highlight_marks_filter_matches() {
+        let filter = LogFilter::new(Some(Regex::new("b+").unwrap()), None, None, true);
+        assert_eq!("a\x1b[1;31mbb\x1b[0mc", filter.highlight("abbc"));
+
+        let filter = LogFilter::new(Some(Regex::new("b+").unwrap()), None, None, false);
+        assert_eq!("abbc", filter.highlight("abbc"));
+    }
+}
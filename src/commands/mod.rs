@@ -1,3 +1,4 @@
+pub mod apply;
 pub mod apps;
 pub mod apps_output;
 pub mod deploy;
@@ -7,7 +8,10 @@ pub mod links_output;
 pub mod links_target;
 pub mod login;
 pub mod logs;
+pub mod parallel;
+pub mod secrets;
 pub mod sqlite;
+pub mod sqlite_output;
 pub mod variables;
 
 use crate::{commands::deploy::login_connection, opts::DEPLOYMENT_ENV_NAME_ENV};
@@ -0,0 +1,108 @@
+//! Running independent cloud API calls concurrently. All calls are made even
+//! if some fail, so that every problem is reported at once.
+use std::future::Future;
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+
+/// The number of cloud API calls made at once. This is the only limit, also
+/// used as the default of the `--concurrency` options of commands that let
+/// users choose their own.
+pub(crate) const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// Runs the tasks, at most `limit` at a time, and fails with the errors of
+/// all tasks that failed, in the order the tasks were given in
+pub(crate) async fn run_all<Fut>(tasks: impl IntoIterator<Item = Fut>, limit: usize) -> Result<()>
+where
+    Fut: Future<Output = Result<()>>,
+{
+    collect_all(tasks, limit).await.map(|_| ())
+}
+
+/// Runs the tasks in the same way as `run_all`, returning their results in
+/// the order the tasks were given in
+pub(crate) async fn collect_all<T, Fut>(
+    tasks: impl IntoIterator<Item = Fut>,
+    limit: usize,
+) -> Result<Vec<T>>
+where
+    Fut: Future<Output = Result<T>>,
+{
+    let results = futures::stream::iter(tasks)
+        .buffered(limit.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    let mut values = Vec::with_capacity(results.len());
+    let mut errors = vec![];
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(e) => errors.push(e),
+        }
+    }
+    combine_errors(errors)?;
+    Ok(values)
+}
+
+/// Combines errors into one error that describes all of them
+pub(crate) fn combine_errors(mut errors: Vec<anyhow::Error>) -> Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        count => {
+            let details = errors
+                .iter()
+                .map(|e| format!("  {e:#}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Err(anyhow!("{count} operations failed:\n{details}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod parallel_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_concurrency_is_bounded() -> Result<()> {
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+        let tasks = (0..20).map(|_| async {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most_running.fetch_max(now, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        run_all(tasks, 3).await?;
+        assert_eq!(3, most_running.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_all_errors_are_reported() {
+        let tasks = (0..5).map(|i| async move {
+            if i % 2 == 0 {
+                anyhow::bail!("task {i} failed")
+            }
+            Ok(())
+        });
+
+        let error = run_all(tasks, 2)
+            .await
+            .expect_err("tasks should have failed");
+        assert_eq!(
+            "3 operations failed:\n  task 0 failed\n  task 2 failed\n  task 4 failed",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_single_error_is_kept_as_is() {
+        let error = combine_errors(vec![anyhow!("cause").context("problem")]).unwrap_err();
+        assert_eq!("problem: cause", format!("{error:#}"));
+    }
+}
@@ -0,0 +1,350 @@
+//! Resolution of variable values that refer to secrets kept elsewhere, such as
+//! `env://NAME`, `file://path` or `vault://kv/data/app#token`, so that secrets
+//! never have to be given on the command line.
+//!
+//! A value that should be set as it is, although it looks like a reference,
+//! is escaped with a leading backslash: `\env://NAME` sets the value to
+//! `env://NAME`. A backslash before anything other than a reference is kept.
+//!
+//! Values that were set as they are before references were supported, such
+//! as `file:///srv/data`, are now read as references, so they must be escaped
+//! to keep their meaning. Each variable read from a reference is reported on
+//! stderr, naming the reference, so that it is visible what was read.
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+
+/// Resolves the references of one scheme to the secrets they refer to
+#[async_trait]
+pub(crate) trait SecretProvider: Send + Sync {
+    /// The scheme of the references the provider resolves, e.g. "vault"
+    fn scheme(&self) -> &str;
+
+    /// Resolves the part of a reference after `scheme://`
+    async fn resolve(&self, location: &str) -> Result<String>;
+}
+
+/// Resolves references in variable values using a set of providers. Values
+/// that are not references to one of the providers are kept as they are.
+pub(crate) struct SecretResolver {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+impl Default for SecretResolver {
+    fn default() -> Self {
+        Self::new(vec![
+            Box::new(EnvProvider),
+            Box::new(FileProvider),
+            Box::new(VaultProvider::from_env()),
+        ])
+    }
+}
+
+impl SecretResolver {
+    pub(crate) fn new(providers: Vec<Box<dyn SecretProvider>>) -> Self {
+        Self { providers }
+    }
+
+    pub(crate) async fn resolve(&self, value: &str) -> Result<String> {
+        if let Some(escaped) = value.strip_prefix('\\') {
+            if self.provider(escaped).is_some() {
+                return Ok(escaped.to_owned());
+            }
+        }
+        match self.provider(value) {
+            Some((provider, location)) => provider.resolve(location).await,
+            None => Ok(value.to_owned()),
+        }
+    }
+
+    /// Whether a value is a reference to one of the providers' secrets
+    pub(crate) fn is_reference(&self, value: &str) -> bool {
+        self.provider(value).is_some()
+    }
+
+    // The provider of the scheme of a reference, and the rest of the reference
+    fn provider<'a>(&self, value: &'a str) -> Option<(&dyn SecretProvider, &'a str)> {
+        let (scheme, location) = value.split_once("://")?;
+        self.providers
+            .iter()
+            .find(|p| p.scheme() == scheme)
+            .map(|p| (p.as_ref(), location))
+    }
+
+    /// Resolves the values of variables, naming the variable on failure
+    pub(crate) async fn resolve_variables(
+        &self,
+        variables: Vec<(String, String)>,
+    ) -> Result<Vec<(String, String)>> {
+        let mut resolved = Vec::with_capacity(variables.len());
+        for (name, value) in variables {
+            if self.is_reference(&value) {
+                eprintln!("Reading the value of variable '{name}' from {value}");
+            }
+            let value = self
+                .resolve(&value)
+                .await
+                .with_context(|| format!("Could not resolve the value of variable '{name}'"))?;
+            resolved.push((name, value));
+        }
+        Ok(resolved)
+    }
+}
+
+/// Resolves `env://NAME` to the value of an environment variable
+struct EnvProvider;
+
+#[async_trait]
+impl SecretProvider for EnvProvider {
+    fn scheme(&self) -> &str {
+        "env"
+    }
+
+    async fn resolve(&self, location: &str) -> Result<String> {
+        std::env::var(location)
+            .with_context(|| format!("Environment variable '{location}' is not set"))
+    }
+}
+
+/// Resolves `file://path` to the contents of a file, without a final newline
+struct FileProvider;
+
+#[async_trait]
+impl SecretProvider for FileProvider {
+    fn scheme(&self) -> &str {
+        "file"
+    }
+
+    async fn resolve(&self, location: &str) -> Result<String> {
+        let contents = tokio::fs::read_to_string(location)
+            .await
+            .with_context(|| format!("Could not read secret file '{location}'"))?;
+        let contents = contents.strip_suffix('\n').unwrap_or(&contents);
+        Ok(contents.strip_suffix('\r').unwrap_or(contents).to_owned())
+    }
+}
+
+/// Resolves `vault://path#field` to a field of a secret in HashiCorp Vault,
+/// reading `path` from the Vault HTTP API. Secrets of both versions of the KV
+/// secrets engine can be read; for version 2 the path includes `data/`, as
+/// in `vault://secret/data/app#token`.
+pub(crate) struct VaultProvider {
+    address: Option<String>,
+    token: Option<String>,
+    namespace: Option<String>,
+}
+
+impl VaultProvider {
+    /// Configures the provider with the environment variables the Vault CLI
+    /// uses
+    pub(crate) fn from_env() -> Self {
+        Self {
+            address: std::env::var("VAULT_ADDR").ok(),
+            token: std::env::var("VAULT_TOKEN").ok(),
+            namespace: std::env::var("VAULT_NAMESPACE").ok(),
+        }
+    }
+}
+
+#[async_trait]
+impl SecretProvider for VaultProvider {
+    fn scheme(&self) -> &str {
+        "vault"
+    }
+
+    async fn resolve(&self, location: &str) -> Result<String> {
+        let address = self
+            .address
+            .as_deref()
+            .context("VAULT_ADDR must be set to read secrets from Vault")?;
+        let token = self
+            .token
+            .as_deref()
+            .context("VAULT_TOKEN must be set to read secrets from Vault")?;
+        let (path, field) = match location.split_once('#') {
+            Some((path, field)) => (path, Some(field)),
+            None => (location, None),
+        };
+
+        let url = format!(
+            "{}/v1/{}",
+            address.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        );
+        let mut request = reqwest::Client::new()
+            .get(&url)
+            .header("X-Vault-Token", token);
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Could not reach Vault at {address}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!("Vault returned {status} for secret '{path}'");
+        }
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .context("Could not parse the response from Vault")?;
+
+        // Version 2 of the KV engine nests the secret in a second `data`
+        let data = body
+            .pointer("/data/data")
+            .filter(|d| d.is_object())
+            .or_else(|| body.get("data"))
+            .and_then(|d| d.as_object())
+            .ok_or_else(|| anyhow!("Vault returned no data for secret '{path}'"))?;
+        let value = match field {
+            Some(field) => data
+                .get(field)
+                .ok_or_else(|| anyhow!("Secret '{path}' has no field '{field}'"))?,
+            None if data.len() == 1 => data.values().next().unwrap(),
+            None => bail!("Secret '{path}' has several fields. Name one with '#field'."),
+        };
+        match value {
+            serde_json::Value::String(s) => Ok(s.clone()),
+            other => Ok(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod secrets_tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Serves a single request, answering with the given status and body if
+    // the request carries the expected token, and returns the request line
+    async fn fake_vault(
+        status: &'static str,
+        body: &'static str,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            let (status, body) = if request
+                .to_lowercase()
+                .contains("x-vault-token: test-token\r\n")
+            {
+                (status, body)
+            } else {
+                ("403 Forbidden", "{}")
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            request.lines().next().unwrap_or_default().to_owned()
+        });
+        (address, server)
+    }
+
+    fn vault(address: String) -> VaultProvider {
+        VaultProvider {
+            address: Some(address),
+            token: Some("test-token".to_owned()),
+            namespace: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vault_kv2_field_is_resolved() -> Result<()> {
+        let (address, server) = fake_vault(
+            "200 OK",
+            r#"{"data": {"data": {"token": "s3cr3t", "user": "me"}, "metadata": {"version": 1}}}"#,
+        )
+        .await;
+        let resolver = SecretResolver::new(vec![Box::new(vault(address))]);
+
+        let value = resolver.resolve("vault://kv/data/app#token").await?;
+        assert_eq!("s3cr3t", value);
+        assert_eq!("GET /v1/kv/data/app HTTP/1.1", server.await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vault_errors_are_reported() {
+        let (address, _server) = fake_vault("404 Not Found", r#"{"errors": []}"#).await;
+        let error = vault(address)
+            .resolve("kv/data/missing#token")
+            .await
+            .expect_err("should have failed for a missing secret")
+            .to_string();
+        assert!(error.contains("404"), "{error}");
+
+        let (address, _server) =
+            fake_vault("200 OK", r#"{"data": {"data": {"a": "1", "b": "2"}}}"#).await;
+        vault(address)
+            .resolve("kv/data/app")
+            .await
+            .expect_err("should have required a field for a secret with several");
+    }
+
+    #[tokio::test]
+    async fn test_env_and_file_references_are_resolved() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("token");
+        std::fs::write(&path, "from-file\n")?;
+        std::env::set_var("SECRETS_TEST_TOKEN", "from-env");
+
+        let resolver = SecretResolver::new(vec![Box::new(EnvProvider), Box::new(FileProvider)]);
+        let resolved = resolver
+            .resolve_variables(vec![
+                ("a".to_owned(), "env://SECRETS_TEST_TOKEN".to_owned()),
+                ("b".to_owned(), format!("file://{}", path.display())),
+                ("c".to_owned(), "https://example.com".to_owned()),
+            ])
+            .await?;
+        assert_eq!(
+            vec![
+                ("a".to_owned(), "from-env".to_owned()),
+                ("b".to_owned(), "from-file".to_owned()),
+                ("c".to_owned(), "https://example.com".to_owned()),
+            ],
+            resolved
+        );
+
+        assert!(resolver.is_reference("env://SECRETS_TEST_TOKEN"));
+        assert!(!resolver.is_reference("\\env://SECRETS_TEST_TOKEN"));
+        assert!(!resolver.is_reference("https://example.com"));
+        assert_eq!(
+            "env://SECRETS_TEST_TOKEN",
+            resolver.resolve("\\env://SECRETS_TEST_TOKEN").await?
+        );
+        assert_eq!(
+            "\\https://example.com",
+            resolver.resolve("\\https://example.com").await?
+        );
+        assert_eq!(
+            "\\\\server\\share",
+            resolver.resolve("\\\\server\\share").await?
+        );
+
+        let error = resolver
+            .resolve_variables(vec![(
+                "d".to_owned(),
+                "env://SECRETS_TEST_UNSET".to_owned(),
+            )])
+            .await
+            .expect_err("should have failed for an unset environment variable");
+        assert_eq!(
+            "Could not resolve the value of variable 'd'",
+            error.to_string()
+        );
+        Ok(())
+    }
+}
@@ -6,12 +6,24 @@ use clap::{Parser, ValueEnum};
 use cloud::CloudClientInterface;
 use cloud_openapi::models::Database;
 
+use std::path::PathBuf;
 use std::str::FromStr;
 
+use self::shell::Shell;
+
+mod dump;
+mod guard;
+mod local;
+mod migrate;
+mod shell;
+mod statements;
+mod transaction;
+
 use crate::commands::links_output::{
     print_json, print_table, prompt_delete_resource, ListFormat, ResourceGroupBy, ResourceLinks,
     ResourceType,
 };
+use crate::commands::sqlite_output::{print_query_result, QueryFormat};
 
 /// Manage Fermyon Cloud SQLite databases
 #[derive(Parser, Debug)]
@@ -23,8 +35,25 @@ pub enum SqliteCommand {
     Delete(DeleteCommand),
     /// Execute SQL statements against a SQLite database
     Execute(ExecuteCommand),
+    /// Write the schema and contents of a SQLite database as SQL statements
+    ///
+    /// The database is read a page of rows at a time rather than in a single
+    /// transaction, so the export is not a point-in-time snapshot: changes made
+    /// while it runs may be partly included. Stop writing to the database for a
+    /// consistent export.
+    Export(ExportCommand),
+    /// Run the SQL statements of a dump against a SQLite database
+    Import(ImportCommand),
     /// List all your SQLite databases
     List(ListCommand),
+    /// Apply versioned SQL migration files to a SQLite database
+    Migrate(MigrateCommand),
+    /// Copy a SQLite database into a local SQLite file, e.g. for use with `spin up`
+    Pull(PullCommand),
+    /// Replace the contents of a SQLite database with those of a local SQLite file
+    Push(PushCommand),
+    /// Start an interactive shell to run SQL statements against a SQLite database
+    Shell(ShellCommand),
     /// Rename a SQLite database. All existing links will automatically link to the database's new name.
     Rename(RenameCommand),
 }
@@ -70,6 +99,188 @@ pub struct ExecuteCommand {
     #[clap(name = "SQL", value_parser = clap::builder::ValueParser::new(disallow_empty))]
     statement: String,
 
+    /// Format of the rows returned by the statement
+    #[clap(value_enum, long = "format", default_value = "table")]
+    format: QueryFormat,
+
+    /// Skips prompt to confirm statements that can destroy data, such as
+    /// DROP TABLE or DELETE without WHERE
+    #[clap(short = 'y', long = "yes", takes_value = false)]
+    yes: bool,
+
+    /// Refuse to run any statement that can change the database
+    #[clap(long = "read-only", takes_value = false)]
+    read_only: bool,
+
+    /// Run all statements in a single transaction, which is rolled back if
+    /// any of them fails
+    #[clap(long = "transaction", takes_value = false)]
+    transaction: bool,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportCommand {
+    /// Name of database to export
+    #[clap(name = "DATABASE", value_parser = clap::builder::ValueParser::new(disallow_empty), required_unless_present = "LABEL", conflicts_with = "LABEL")]
+    database: Option<String>,
+
+    /// Label of database to export
+    #[clap(name = "LABEL", short = 'l', long = "label", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "APP")]
+    label: Option<String>,
+
+    /// App to which label relates
+    #[clap(name = "APP", short = 'a', long = "app", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "LABEL")]
+    app: Option<String>,
+
+    /// File to write the dump to. If omitted, the dump is written to stdout.
+    #[clap(short = 'o', long = "output")]
+    output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportCommand {
+    /// Name of database to import into
+    #[clap(name = "DATABASE", short = 'd', long = "database", value_parser = clap::builder::ValueParser::new(disallow_empty), group = "db", required_unless_present = "LABEL")]
+    database: Option<String>,
+
+    /// Label of database to import into
+    #[clap(name = "LABEL", short = 'l', long = "label", value_parser = clap::builder::ValueParser::new(disallow_empty), group = "db", requires = "APP", required_unless_present = "DATABASE")]
+    label: Option<String>,
+
+    /// App to which label relates
+    #[clap(name = "APP", short = 'a', long = "app", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "LABEL", conflicts_with = "DATABASE")]
+    app: Option<String>,
+
+    /// The dump to import, as written by `export` or the `.dump` command of
+    /// the sqlite3 shell
+    #[clap(name = "FILE")]
+    file: PathBuf,
+
+    /// The number of statements to send at once
+    #[clap(long = "batch-size", default_value = dump::DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct MigrateCommand {
+    /// Name of database to migrate
+    #[clap(name = "DATABASE", short = 'd', long = "database", value_parser = clap::builder::ValueParser::new(disallow_empty), group = "db", required_unless_present = "LABEL")]
+    database: Option<String>,
+
+    /// Label of database to migrate
+    #[clap(name = "LABEL", short = 'l', long = "label", value_parser = clap::builder::ValueParser::new(disallow_empty), group = "db", requires = "APP", required_unless_present = "DATABASE")]
+    label: Option<String>,
+
+    /// App to which label relates
+    #[clap(name = "APP", short = 'a', long = "app", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "LABEL", conflicts_with = "DATABASE")]
+    app: Option<String>,
+
+    /// Directory of migration files. Each file is named after its version,
+    /// e.g. 0001_create_users.sql, and migrations are applied in version order.
+    /// Applied migrations are recorded in the `_spin_cloud_migrations` table.
+    #[clap(name = "DIR")]
+    dir: PathBuf,
+
+    /// Show which migrations have been applied instead of applying any
+    #[clap(
+        name = "status",
+        long = "status",
+        takes_value = false,
+        conflicts_with = "dry-run"
+    )]
+    status: bool,
+
+    /// Show which migrations would be applied without applying them
+    #[clap(name = "dry-run", long = "dry-run", takes_value = false)]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct PullCommand {
+    /// Name of database to pull
+    #[clap(name = "DATABASE", value_parser = clap::builder::ValueParser::new(disallow_empty), required_unless_present = "LABEL", conflicts_with = "LABEL")]
+    database: Option<String>,
+
+    /// Label of database to pull
+    #[clap(name = "LABEL", short = 'l', long = "label", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "APP")]
+    label: Option<String>,
+
+    /// App to which label relates
+    #[clap(name = "APP", short = 'a', long = "app", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "LABEL")]
+    app: Option<String>,
+
+    /// Local SQLite file to write the database to
+    #[clap(long = "to", default_value = local::DEFAULT_LOCAL_DATABASE)]
+    to: PathBuf,
+
+    /// Replace the local file if it already exists
+    #[clap(long = "force", takes_value = false)]
+    force: bool,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct PushCommand {
+    /// Name of database to push to
+    #[clap(name = "DATABASE", value_parser = clap::builder::ValueParser::new(disallow_empty), required_unless_present = "LABEL", conflicts_with = "LABEL")]
+    database: Option<String>,
+
+    /// Label of database to push to
+    #[clap(name = "LABEL", short = 'l', long = "label", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "APP")]
+    label: Option<String>,
+
+    /// App to which label relates
+    #[clap(name = "APP", short = 'a', long = "app", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "LABEL")]
+    app: Option<String>,
+
+    /// Local SQLite file to read the database from
+    #[clap(long = "from", default_value = local::DEFAULT_LOCAL_DATABASE)]
+    from: PathBuf,
+
+    /// Drop the existing tables and views of the database before pushing
+    #[clap(long = "force", takes_value = false)]
+    force: bool,
+
+    /// The number of statements to send at once
+    #[clap(long = "batch-size", default_value = dump::DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct ShellCommand {
+    /// Name of database to open
+    #[clap(name = "DATABASE", short = 'd', long = "database", value_parser = clap::builder::ValueParser::new(disallow_empty), group = "db", required_unless_present = "LABEL")]
+    database: Option<String>,
+
+    /// Label of database to open
+    #[clap(name = "LABEL", short = 'l', long = "label", value_parser = clap::builder::ValueParser::new(disallow_empty), group = "db", requires = "APP", required_unless_present = "DATABASE")]
+    label: Option<String>,
+
+    /// App to which label relates
+    #[clap(name = "APP", short = 'a', long = "app", value_parser = clap::builder::ValueParser::new(disallow_empty), requires = "LABEL", conflicts_with = "DATABASE")]
+    app: Option<String>,
+
+    /// Format of query results. Can be changed in the shell with `.mode`.
+    #[clap(value_enum, long = "format", default_value = "table")]
+    format: QueryFormat,
+
     #[clap(flatten)]
     common: CommonArgs,
 }
@@ -156,7 +367,31 @@ impl SqliteCommand {
                 let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
                 cmd.run(client).await
             }
+            Self::Export(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
+            Self::Import(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
             Self::List(cmd) => cmd.run().await,
+            Self::Migrate(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
+            Self::Pull(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
+            Self::Push(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
+            Self::Shell(cmd) => {
+                let client = create_cloud_client(cmd.common.deployment_env_id.as_deref()).await?;
+                cmd.run(client).await
+            }
             Self::Rename(cmd) => cmd.run().await,
         }
     }
@@ -208,28 +443,127 @@ impl DeleteCommand {
 
 impl ExecuteCommand {
     pub async fn run(self, client: impl CloudClientInterface) -> Result<()> {
-        let target = ResourceTarget::from_inputs(&self.database, &self.label, &self.app)?;
-        let list = client
-            .get_databases(None)
-            .await
-            .context("Problem fetching databases")?;
-        let database = target
-            .find_in(to_resource_links(list), ResourceType::Database)?
-            .name;
         let statement = if let Some(path) = self.statement.strip_prefix('@') {
             std::fs::read_to_string(path)
                 .with_context(|| format!("could not read sql file at '{path}'"))?
         } else {
             self.statement
         };
-        client
-            .execute_sql(database, statement)
-            .await
-            .context("Problem executing SQL")?;
+        if self.read_only {
+            guard::ensure_read_only(&statement)?;
+        }
+        let target = ResourceTarget::from_inputs(&self.database, &self.label, &self.app)?;
+        let database = find_database(&client, &target).await?;
+        let destructive = guard::destructive_statements(&statement);
+        if !destructive.is_empty()
+            && !self.yes
+            && !guard::prompt_destructive(&database, &destructive)?
+        {
+            return Ok(());
+        }
+        let result = if self.transaction {
+            transaction::execute_in_transaction(&client, &database, &statement).await
+        } else {
+            client.execute_sql(database, statement).await
+        }
+        .context("Problem executing SQL")?;
+        print_query_result(&result, self.format)
+    }
+}
+
+impl ExportCommand {
+    pub async fn run(self, client: impl CloudClientInterface) -> Result<()> {
+        let target = ResourceTarget::from_inputs(&self.database, &self.label, &self.app)?;
+        let database = find_database(&client, &target).await?;
+        let dump = dump::export(&dump::CloudDatabase {
+            client: &client,
+            database: &database,
+        })
+        .await?;
+        match &self.output {
+            Some(output) => {
+                std::fs::write(output, dump)
+                    .with_context(|| format!("Could not write {}", output.display()))?;
+                println!("Database \"{database}\" exported to {}", output.display());
+            }
+            None => print!("{dump}"),
+        }
+        Ok(())
+    }
+}
+
+impl ImportCommand {
+    pub async fn run(self, client: impl CloudClientInterface) -> Result<()> {
+        if self.batch_size == 0 {
+            bail!("Batch size must be at least 1");
+        }
+        let sql = std::fs::read_to_string(&self.file)
+            .with_context(|| format!("could not read sql file at '{}'", self.file.display()))?;
+        let target = ResourceTarget::from_inputs(&self.database, &self.label, &self.app)?;
+        let database = find_database(&client, &target).await?;
+        let count = dump::import(&client, &database, &sql, self.batch_size).await?;
+        println!("Ran {count} statements against database \"{database}\"");
         Ok(())
     }
 }
 
+impl MigrateCommand {
+    pub async fn run(self, client: impl CloudClientInterface) -> Result<()> {
+        let migrations = migrate::read_migrations(&self.dir)?;
+        let target = ResourceTarget::from_inputs(&self.database, &self.label, &self.app)?;
+        let database = find_database(&client, &target).await?;
+        if self.status {
+            migrate::print_status(&client, &database, &migrations).await
+        } else {
+            migrate::migrate(&client, &database, &migrations, self.dry_run).await
+        }
+    }
+}
+
+impl PullCommand {
+    pub async fn run(self, client: impl CloudClientInterface) -> Result<()> {
+        let target = ResourceTarget::from_inputs(&self.database, &self.label, &self.app)?;
+        let database = find_database(&client, &target).await?;
+        local::pull(&client, &database, &self.to, self.force).await?;
+        println!("Database \"{database}\" pulled to {}", self.to.display());
+        Ok(())
+    }
+}
+
+impl PushCommand {
+    pub async fn run(self, client: impl CloudClientInterface) -> Result<()> {
+        if self.batch_size == 0 {
+            bail!("Batch size must be at least 1");
+        }
+        let target = ResourceTarget::from_inputs(&self.database, &self.label, &self.app)?;
+        let database = find_database(&client, &target).await?;
+        local::push(&client, &database, &self.from, self.force, self.batch_size).await?;
+        println!("{} pushed to database \"{database}\"", self.from.display());
+        Ok(())
+    }
+}
+
+impl ShellCommand {
+    pub async fn run(self, client: impl CloudClientInterface) -> Result<()> {
+        let target = ResourceTarget::from_inputs(&self.database, &self.label, &self.app)?;
+        let database = find_database(&client, &target).await?;
+        Shell::new(&client, database, self.format).run().await
+    }
+}
+
+async fn find_database(
+    client: &impl CloudClientInterface,
+    target: &ResourceTarget,
+) -> Result<String> {
+    let list = client
+        .get_databases(None)
+        .await
+        .context("Problem fetching databases")?;
+    Ok(target
+        .find_in(to_resource_links(list), ResourceType::Database)?
+        .name)
+}
+
 impl ListCommand {
     pub async fn run(self) -> Result<()> {
         if let (ListFormat::Json, Some(_)) = (&self.format, self.group_by) {
@@ -389,6 +723,10 @@ mod sqlite_tests {
             app: None,
             common: Default::default(),
             statement: sql.to_owned(),
+            format: QueryFormat::Table,
+            yes: false,
+            read_only: false,
+            transaction: false,
         };
 
         let mut mock = MockCloudClientInterface::new();
@@ -396,7 +734,7 @@ mod sqlite_tests {
             .returning(move |_| Ok(vec![Database::new(db.to_string(), vec![])]));
         mock.expect_execute_sql()
             .withf(move |dbarg, sqlarg| dbarg == db && sqlarg == sql)
-            .returning(|_, _| Ok(()));
+            .returning(|_, _| Ok(Default::default()));
 
         command.run(mock).await
     }
@@ -413,6 +751,10 @@ mod sqlite_tests {
             app: None,
             common: Default::default(),
             statement: sql.to_owned(),
+            format: QueryFormat::Table,
+            yes: false,
+            read_only: false,
+            transaction: false,
         };
 
         let mut mock = MockCloudClientInterface::new();
@@ -442,6 +784,10 @@ mod sqlite_tests {
             app: Some(app.to_string()),
             common: Default::default(),
             statement: sql.to_owned(),
+            format: QueryFormat::Table,
+            yes: false,
+            read_only: false,
+            transaction: false,
         };
 
         let mut mock = MockCloudClientInterface::new();
@@ -449,7 +795,7 @@ mod sqlite_tests {
             .returning(move |_| Ok(fake_dbs()));
         mock.expect_execute_sql()
             .withf(move |dbarg, sqlarg| dbarg == "db2" && sqlarg == sql)
-            .returning(|_, _| Ok(()));
+            .returning(|_, _| Ok(Default::default()));
 
         command.run(mock).await
     }
@@ -466,6 +812,10 @@ mod sqlite_tests {
             app: Some(app.to_string()),
             common: Default::default(),
             statement: sql.to_owned(),
+            format: QueryFormat::Table,
+            yes: false,
+            read_only: false,
+            transaction: false,
         };
 
         let mut mock = MockCloudClientInterface::new();
@@ -483,6 +833,56 @@ mod sqlite_tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_execute_read_only_rejects_writes_before_executing() -> Result<()> {
+        let command = ExecuteCommand {
+            database: Some("db1".to_string()),
+            label: None,
+            app: None,
+            common: Default::default(),
+            statement: "SELECT * FROM test; DELETE FROM test WHERE id = 1".to_owned(),
+            format: QueryFormat::Table,
+            yes: true,
+            read_only: true,
+            transaction: false,
+        };
+
+        // Neither the database nor the statement should be touched
+        let mock = MockCloudClientInterface::new();
+        let err = command
+            .run(mock)
+            .await
+            .expect_err("exec should have errored but did not");
+        assert!(err.to_string().contains("read-only"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_execute_destructive_statement_with_yes_is_executed() -> Result<()> {
+        let sql = "DROP TABLE test";
+        let command = ExecuteCommand {
+            database: Some("db1".to_string()),
+            label: None,
+            app: None,
+            common: Default::default(),
+            statement: sql.to_owned(),
+            format: QueryFormat::Table,
+            yes: true,
+            read_only: false,
+            transaction: false,
+        };
+
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_get_databases()
+            .returning(move |_| Ok(fake_dbs()));
+        mock.expect_execute_sql()
+            .withf(move |dbarg, sqlarg| dbarg == "db1" && sqlarg == sql)
+            .times(1)
+            .returning(|_, _| Ok(Default::default()));
+
+        command.run(mock).await
+    }
+
     fn fake_dbs() -> Vec<Database> {
         vec![
             Database::new(
@@ -0,0 +1,387 @@
+//! Export of cloud databases to SQL dumps, and import of dumps into cloud
+//! databases. Dumps are in the same format as the `.dump` command of the
+//! `sqlite3` shell, so they can be moved between cloud and local databases.
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use cloud::models::SqlQueryResult;
+use cloud::CloudClientInterface;
+
+use super::statements::split_statements;
+
+/// The number of rows of a table fetched at once when exporting
+const EXPORT_PAGE_SIZE: usize = 500;
+
+/// The number of statements sent at once if no other batch size is given
+pub(super) const DEFAULT_BATCH_SIZE: &str = "100";
+
+/// A database that a dump can be exported from
+#[async_trait(?Send)]
+pub(super) trait DumpSource {
+    async fn query(&self, sql: String) -> Result<SqlQueryResult>;
+}
+
+/// A cloud database
+pub(super) struct CloudDatabase<'a, C> {
+    pub client: &'a C,
+    pub database: &'a str,
+}
+
+#[async_trait(?Send)]
+impl<C: CloudClientInterface> DumpSource for CloudDatabase<'_, C> {
+    async fn query(&self, sql: String) -> Result<SqlQueryResult> {
+        self.client.execute_sql(self.database.to_owned(), sql).await
+    }
+}
+
+/// Writes the schema and rows of a database as a dump. The schema and each
+/// page of rows are read by separate queries, so a database written to
+/// meanwhile may be dumped in an inconsistent state.
+pub(super) async fn export(source: &impl DumpSource) -> Result<String> {
+    let schema = source
+        .query(
+            "SELECT type, name, sql FROM sqlite_master WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' ORDER BY CASE type WHEN 'table' THEN 0 ELSE 1 END, name".to_owned(),
+        )
+        .await
+        .context("Problem reading the database schema")?;
+
+    let mut dump = format!("{FOREIGN_KEYS_OFF}\nBEGIN TRANSACTION;\n");
+    // Tables and their rows come first, so that indexes, triggers and views
+    // are created once the data is in place
+    let mut deferred = vec![];
+    let mut has_autoincrement = false;
+    for row in &schema.rows {
+        let text = |index: usize| row.get(index).and_then(|v| v.as_str()).unwrap_or_default();
+        let (kind, name, sql) = (text(0), text(1), text(2));
+        if kind != "table" {
+            deferred.push(sql);
+            continue;
+        }
+        dump.push_str(&format!("{sql};\n"));
+        let upper = sql.to_uppercase();
+        has_autoincrement |= upper.contains("AUTOINCREMENT");
+        let without_rowid = upper
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .contains("WITHOUT ROWID");
+        dump_rows(source, name, without_rowid, &mut dump).await?;
+    }
+    if has_autoincrement {
+        dump.push_str("DELETE FROM sqlite_sequence;\n");
+        dump_rows(source, "sqlite_sequence", false, &mut dump).await?;
+    }
+    for sql in deferred {
+        dump.push_str(&format!("{sql};\n"));
+    }
+    dump.push_str("COMMIT;\n");
+    Ok(dump)
+}
+
+// Pages through the rows of a table in key order, each page starting after
+// the last row of the one before, so that no row is skipped or repeated. Tables
+// without a rowid are ordered by their primary key.
+async fn dump_rows(
+    source: &impl DumpSource,
+    table: &str,
+    without_rowid: bool,
+    dump: &mut String,
+) -> Result<()> {
+    let (select, key_columns, order_by) = if without_rowid {
+        let key = primary_key(source, table).await?;
+        let names = key
+            .iter()
+            .map(|(_, name)| quote_identifier(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let indexes = key.into_iter().map(|(index, _)| index).collect::<Vec<_>>();
+        ("*", indexes, names)
+    } else {
+        ("_rowid_, *", vec![0], "_rowid_".to_owned())
+    };
+    // The rowid is selected only to page by, and is not part of the row
+    let skip = if without_rowid { 0 } else { 1 };
+    let table = quote_identifier(table);
+
+    let mut after = String::new();
+    loop {
+        let result = source
+            .query(format!(
+                "SELECT {select} FROM {table}{after} ORDER BY {order_by} LIMIT {EXPORT_PAGE_SIZE}"
+            ))
+            .await
+            .with_context(|| format!("Problem reading the rows of table {table}"))?;
+        for row in &result.rows {
+            let values = row[skip.min(row.len())..]
+                .iter()
+                .map(sql_literal)
+                .collect::<Vec<_>>()
+                .join(",");
+            dump.push_str(&format!("INSERT INTO {table} VALUES({values});\n"));
+        }
+        let Some(last) = result.rows.last() else {
+            break;
+        };
+        if result.rows.len() < EXPORT_PAGE_SIZE {
+            break;
+        }
+        let last_key = key_columns
+            .iter()
+            .map(|&index| last.get(index).map_or("NULL".to_owned(), sql_literal))
+            .collect::<Vec<_>>()
+            .join(", ");
+        after = format!(" WHERE ({order_by}) > ({last_key})");
+    }
+    Ok(())
+}
+
+// The columns of the primary key of a table, in key order, with their
+// positions among the columns of the table
+async fn primary_key(source: &impl DumpSource, table: &str) -> Result<Vec<(usize, String)>> {
+    let result = source
+        .query(format!(
+            "SELECT cid, name FROM pragma_table_info('{}') WHERE pk > 0 ORDER BY pk",
+            table.replace('\'', "''")
+        ))
+        .await
+        .with_context(|| format!("Problem reading the primary key of table {table}"))?;
+    let key = result
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let index = row.first()?.as_u64()? as usize;
+            let name = row.get(1)?.as_str()?;
+            Some((index, name.to_owned()))
+        })
+        .collect::<Vec<_>>();
+    anyhow::ensure!(!key.is_empty(), "Table {table} has no primary key");
+    Ok(key)
+}
+
+pub(super) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_literal(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "NULL".to_owned(),
+        serde_json::Value::Bool(b) => (*b as u8).to_string(),
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+        // Blobs are returned as arrays of bytes
+        serde_json::Value::Array(bytes)
+            if bytes.iter().all(|b| b.as_u64().is_some_and(|b| b <= 255)) =>
+        {
+            let hex = bytes
+                .iter()
+                .filter_map(|b| b.as_u64())
+                .map(|b| format!("{b:02X}"))
+                .collect::<String>();
+            format!("X'{hex}'")
+        }
+        other => format!("'{}'", other.to_string().replace('\'', "''")),
+    }
+}
+
+/// Runs the statements of a dump in batches, and returns how many were run.
+/// Transaction statements in the dump are skipped, since a transaction cannot
+/// span batches. Foreign key checks are turned off for every batch, as each
+/// may run on a different connection and tables may refer to tables that have
+/// not been filled yet.
+pub(super) async fn import(
+    client: &impl CloudClientInterface,
+    database: &str,
+    sql: &str,
+    batch_size: usize,
+) -> Result<usize> {
+    let statements = split_statements(sql)
+        .into_iter()
+        .filter(|s| !is_transaction_statement(&s.text) && !is_foreign_keys_off(&s.text))
+        .collect::<Vec<_>>();
+
+    for batch in statements.chunks(batch_size.max(1)) {
+        let script = std::iter::once(FOREIGN_KEYS_OFF.to_owned())
+            .chain(batch.iter().map(|s| format!("{};", s.text)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (first, last) = (batch[0].line, batch[batch.len() - 1].line);
+        client
+            .execute_sql(database.to_owned(), script)
+            .await
+            .with_context(|| {
+                format!("Problem running the statements from line {first} to line {last}")
+            })?;
+    }
+    Ok(statements.len())
+}
+
+const FOREIGN_KEYS_OFF: &str = "PRAGMA foreign_keys=OFF;";
+
+fn is_foreign_keys_off(statement: &str) -> bool {
+    let statement = statement
+        .split_whitespace()
+        .collect::<String>()
+        .to_uppercase();
+    matches!(
+        statement.strip_prefix("PRAGMAFOREIGN_KEYS="),
+        Some("OFF" | "0" | "FALSE" | "NO")
+    )
+}
+
+pub(super) fn is_transaction_statement(statement: &str) -> bool {
+    let first_word = statement
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_uppercase();
+    matches!(first_word.as_str(), "BEGIN" | "COMMIT" | "END" | "ROLLBACK")
+}
+
+#[cfg(test)]
+mod dump_tests {
+    use super::*;
+    use cloud::models::SqlQueryResult;
+    use cloud::MockCloudClientInterface;
+    use serde_json::json;
+
+    #[test]
+    fn test_sql_literals() {
+        assert_eq!("NULL", sql_literal(&json!(null)));
+        assert_eq!("42", sql_literal(&json!(42)));
+        assert_eq!("1.5", sql_literal(&json!(1.5)));
+        assert_eq!("'it''s'", sql_literal(&json!("it's")));
+        assert_eq!("X'00FF'", sql_literal(&json!([0, 255])));
+    }
+
+    #[tokio::test]
+    async fn test_export_writes_schema_and_rows() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s.contains("FROM sqlite_master"))
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![
+                        vec![
+                            json!("table"),
+                            json!("notes"),
+                            json!("CREATE TABLE notes (id INTEGER, body TEXT)"),
+                        ],
+                        vec![
+                            json!("index"),
+                            json!("notes_body"),
+                            json!("CREATE INDEX notes_body ON notes (body)"),
+                        ],
+                    ],
+                    ..Default::default()
+                })
+            });
+        mock.expect_execute_sql()
+            .withf(|_, s| s.starts_with("SELECT _rowid_, * FROM \"notes\" ORDER BY _rowid_"))
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![vec![json!(7), json!(1), json!("hello")]],
+                    ..Default::default()
+                })
+            });
+
+        let dump = export(&CloudDatabase {
+            client: &mock,
+            database: "db1",
+        })
+        .await?;
+        assert_eq!(
+            "PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;\nCREATE TABLE notes (id INTEGER, body TEXT);\nINSERT INTO \"notes\" VALUES(1,'hello');\nCREATE INDEX notes_body ON notes (body);\nCOMMIT;\n",
+            dump
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_runs_statements_in_batches() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s == "PRAGMA foreign_keys=OFF;\nCREATE TABLE t (x);")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(Default::default()));
+        mock.expect_execute_sql()
+            .withf(|_, s| s == "PRAGMA foreign_keys=OFF;\nINSERT INTO t VALUES('a;b');")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(Default::default()));
+
+        let count = import(
+            &mock,
+            "db1",
+            "PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;\nCREATE TABLE t (x);\nINSERT INTO t VALUES('a;b');\nCOMMIT;\n",
+            1,
+        )
+        .await?;
+        assert_eq!(2, count);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_pages_after_the_last_row() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s.contains("FROM sqlite_master"))
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![vec![
+                        json!("table"),
+                        json!("t"),
+                        json!("CREATE TABLE t (x)"),
+                    ]],
+                    ..Default::default()
+                })
+            });
+        mock.expect_execute_sql()
+            .withf(|_, s| s == "SELECT _rowid_, * FROM \"t\" ORDER BY _rowid_ LIMIT 500")
+            .times(1)
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: (1..=500).map(|i| vec![json!(i * 2), json!(i)]).collect(),
+                    ..Default::default()
+                })
+            });
+        mock.expect_execute_sql()
+            .withf(|_, s| {
+                s == "SELECT _rowid_, * FROM \"t\" WHERE (_rowid_) > (1000) ORDER BY _rowid_ LIMIT 500"
+            })
+            .times(1)
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![vec![json!(1002), json!(501)]],
+                    ..Default::default()
+                })
+            });
+
+        let dump = export(&CloudDatabase {
+            client: &mock,
+            database: "db1",
+        })
+        .await?;
+        assert_eq!(501, dump.matches("INSERT INTO").count());
+        assert!(dump.contains("INSERT INTO \"t\" VALUES(501);"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tables_without_rowid_are_paged_by_primary_key() -> Result<()> {
+        let connection = rusqlite::Connection::open_in_memory()?;
+        connection.execute_batch(
+            "CREATE TABLE pairs (a TEXT, b INTEGER, PRIMARY KEY (b, a)) WITHOUT ROWID;
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1200)
+            INSERT INTO pairs SELECT 'x' || i, i % 7 FROM n;",
+        )?;
+
+        let dump = export(&connection).await?;
+        let copy = rusqlite::Connection::open_in_memory()?;
+        copy.execute_batch(&dump)?;
+        let count: usize =
+            copy.query_row("SELECT count(DISTINCT a) FROM pairs", [], |r| r.get(0))?;
+        assert_eq!(1200, count);
+        Ok(())
+    }
+}
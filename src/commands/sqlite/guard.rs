@@ -0,0 +1,307 @@
+//! Classification of SQL statements by what they can do to a database, so that
+//! destructive statements are confirmed and writes can be refused altogether.
+use anyhow::{bail, Result};
+use dialoguer::Input;
+use std::io::IsTerminal;
+
+use super::statements::{split_statements, Statement};
+
+/// What running a statement can do to a database
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(super) enum StatementKind {
+    /// Reads data or controls transactions, without changing anything
+    Read,
+    /// Changes the schema or data in a way that does not lose data wholesale
+    Write,
+    /// Drops schema objects or changes every row of a table
+    Destructive,
+}
+
+/// A word or symbol of a statement outside of quotes and comments, and how
+/// deeply it is nested in parentheses
+#[derive(Debug, PartialEq)]
+struct Token {
+    text: String,
+    depth: usize,
+}
+
+pub(super) fn classify(statement: &str) -> StatementKind {
+    let tokens = tokenize(statement);
+    let top_level = tokens
+        .iter()
+        .filter(|t| t.depth == 0)
+        .map(|t| t.text.as_str())
+        .collect::<Vec<_>>();
+    let Some(&first) = top_level.first() else {
+        return StatementKind::Read;
+    };
+    // The statement proper follows the common table expressions of a WITH
+    let (verb, rest) = if first == "WITH" {
+        match top_level.iter().position(|t| {
+            matches!(
+                *t,
+                "SELECT" | "VALUES" | "INSERT" | "REPLACE" | "UPDATE" | "DELETE"
+            )
+        }) {
+            Some(index) => (top_level[index], &top_level[index + 1..]),
+            None => return StatementKind::Write,
+        }
+    } else {
+        (first, &top_level[1..])
+    };
+    let has = |word: &str| rest.contains(&word);
+
+    match verb {
+        "SELECT" | "VALUES" | "EXPLAIN" => StatementKind::Read,
+        "BEGIN" | "COMMIT" | "END" | "ROLLBACK" | "SAVEPOINT" | "RELEASE" => StatementKind::Read,
+        "PRAGMA" => classify_pragma(&tokens[1..]),
+        "DROP" => StatementKind::Destructive,
+        "DELETE" | "UPDATE" if !has("WHERE") => StatementKind::Destructive,
+        "ALTER" if has("DROP") => StatementKind::Destructive,
+        _ => StatementKind::Write,
+    }
+}
+
+/// Pragmas that only report on the database, whatever their argument
+const REPORTING_PRAGMAS: &[&str] = &[
+    "COLLATION_LIST",
+    "COMPILE_OPTIONS",
+    "DATABASE_LIST",
+    "FOREIGN_KEY_CHECK",
+    "FOREIGN_KEY_LIST",
+    "FUNCTION_LIST",
+    "INDEX_INFO",
+    "INDEX_LIST",
+    "INDEX_XINFO",
+    "INTEGRITY_CHECK",
+    "MODULE_LIST",
+    "PRAGMA_LIST",
+    "QUICK_CHECK",
+    "TABLE_INFO",
+    "TABLE_LIST",
+    "TABLE_XINFO",
+];
+
+/// Pragmas that change the database even when given no argument
+const ACTING_PRAGMAS: &[&str] = &[
+    "INCREMENTAL_VACUUM",
+    "OPTIMIZE",
+    "SHRINK_MEMORY",
+    "WAL_CHECKPOINT",
+];
+
+// Classifies the tokens after PRAGMA. Other than the reporting pragmas, a
+// pragma given an argument, as in `name = value` or `name(value)`, sets
+// something, and without one it gets the current setting.
+fn classify_pragma(tokens: &[Token]) -> StatementKind {
+    // The name may be qualified with a schema, as in main.user_version
+    let tokens = match tokens {
+        [_schema, dot, rest @ ..] if dot.text == "." => rest,
+        tokens => tokens,
+    };
+    let Some((name, argument)) = tokens.split_first() else {
+        return StatementKind::Read;
+    };
+    if REPORTING_PRAGMAS.contains(&name.text.as_str()) {
+        StatementKind::Read
+    } else if !argument.is_empty() || ACTING_PRAGMAS.contains(&name.text.as_str()) {
+        StatementKind::Write
+    } else {
+        StatementKind::Read
+    }
+}
+
+fn tokenize(statement: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut depth = 0usize;
+    let mut word = String::new();
+    let mut chars = statement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            word.extend(c.to_uppercase());
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push(Token {
+                text: std::mem::take(&mut word),
+                depth,
+            });
+        }
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = None;
+                for c in chars.by_ref() {
+                    if previous == Some('*') && c == '/' {
+                        break;
+                    }
+                    previous = Some(c);
+                }
+            }
+            // Quoted strings and identifiers never contain keywords. Doubled
+            // quotes inside them read as two quoted parts, which is harmless.
+            '\'' | '"' | '`' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                for c in chars.by_ref() {
+                    if c == close {
+                        break;
+                    }
+                }
+                tokens.push(Token {
+                    text: c.to_string(),
+                    depth,
+                });
+            }
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            c if c.is_whitespace() => {}
+            c => tokens.push(Token {
+                text: c.to_string(),
+                depth,
+            }),
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token { text: word, depth });
+    }
+    tokens
+}
+
+/// Fails on the first statement of the SQL that is not read-only
+pub(super) fn ensure_read_only(sql: &str) -> Result<()> {
+    if let Some(statement) = split_statements(sql)
+        .into_iter()
+        .find(|s| classify(&s.text) != StatementKind::Read)
+    {
+        bail!(
+            "Refusing to run the statement on line {} in read-only mode: {}",
+            statement.line,
+            statement.text
+        );
+    }
+    Ok(())
+}
+
+pub(super) fn destructive_statements(sql: &str) -> Vec<Statement> {
+    split_statements(sql)
+        .into_iter()
+        .filter(|s| classify(&s.text) == StatementKind::Destructive)
+        .collect()
+}
+
+/// Asks the user to confirm destructive statements by typing the name of the
+/// database, and returns whether they did
+pub(super) fn prompt_destructive(database: &str, statements: &[Statement]) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        bail!(
+            "The SQL contains statements that can destroy data in database \"{database}\". Use --yes to run it without confirmation."
+        );
+    }
+    let mut prompt =
+        format!("The following statements can destroy data in database \"{database}\":\n");
+    for statement in statements {
+        prompt.push_str(&format!("  line {}: {}\n", statement.line, statement.text));
+    }
+    prompt.push_str(&format!(
+        "The action is irreversible. Please type \"{database}\" for confirmation"
+    ));
+    let answer = Input::<String>::new().with_prompt(prompt).interact_text()?;
+    if answer != database {
+        println!("Invalid confirmation. Will not execute SQL.");
+        Ok(false)
+    } else {
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod guard_tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_are_classified_as_read() {
+        for sql in [
+            "SELECT * FROM users WHERE name = 'DROP TABLE users'",
+            "select count(*) from users",
+            "WITH recent AS (SELECT * FROM users) SELECT * FROM recent",
+            "PRAGMA table_info(users)",
+            "EXPLAIN QUERY PLAN DELETE FROM users",
+            "BEGIN",
+        ] {
+            assert_eq!(StatementKind::Read, classify(sql), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_only_pragmas_that_report_or_get_are_read() {
+        for sql in [
+            "PRAGMA main.table_info(users)",
+            "PRAGMA index_list('users')",
+            "PRAGMA foreign_key_list(users)",
+            "PRAGMA integrity_check(10)",
+            "PRAGMA user_version",
+            "pragma main.journal_mode",
+        ] {
+            assert_eq!(StatementKind::Read, classify(sql), "{sql}");
+        }
+        for sql in [
+            "PRAGMA user_version(5)",
+            "PRAGMA main.user_version = 5",
+            "PRAGMA journal_mode(WAL)",
+            "PRAGMA foreign_keys(OFF)",
+            "PRAGMA wal_checkpoint(TRUNCATE)",
+            "PRAGMA optimize",
+            "PRAGMA incremental_vacuum",
+        ] {
+            assert_eq!(StatementKind::Write, classify(sql), "{sql}");
+            ensure_read_only(sql).expect_err(sql);
+        }
+    }
+
+    #[test]
+    fn test_writes_are_classified_as_write() {
+        for sql in [
+            "INSERT INTO users VALUES ('drop')",
+            "CREATE TABLE users (id INTEGER)",
+            "DELETE FROM users WHERE id = 1",
+            "UPDATE users SET name = 'x' -- WHERE is needed\n WHERE id = 1",
+            "ALTER TABLE users ADD COLUMN email TEXT",
+            "PRAGMA foreign_keys = OFF",
+            "WITH old AS (SELECT id FROM users) DELETE FROM users WHERE id IN old",
+        ] {
+            assert_eq!(StatementKind::Write, classify(sql), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_destructive_statements_are_classified_as_destructive() {
+        for sql in [
+            "DROP TABLE users",
+            "drop index if exists users_name",
+            "DELETE FROM users",
+            "UPDATE users SET name = (SELECT name FROM admins WHERE id = 1)",
+            "ALTER TABLE users DROP COLUMN email",
+            "WITH ids AS (SELECT 1 WHERE 1) DELETE FROM users",
+            "DELETE FROM users /* WHERE id = 1 */",
+        ] {
+            assert_eq!(StatementKind::Destructive, classify(sql), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_read_only_reports_first_writing_statement() {
+        ensure_read_only("SELECT 1;\nSELECT 'DELETE';").unwrap();
+        let error = ensure_read_only("SELECT 1;\nINSERT INTO t VALUES (1);")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("line 2"), "{error}");
+    }
+}
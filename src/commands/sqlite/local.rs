@@ -0,0 +1,265 @@
+//! Copying of cloud databases to and from local SQLite files, such as the
+//! one `spin up` uses for an app's default database
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use cloud::models::{SqlColumn, SqlQueryResult};
+use cloud::CloudClientInterface;
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+
+use super::dump::{self, quote_identifier, CloudDatabase, DumpSource};
+
+/// Where `spin up` keeps the default database of an app
+pub(super) const DEFAULT_LOCAL_DATABASE: &str = ".spin/sqlite_db.db";
+
+#[async_trait(?Send)]
+impl DumpSource for Connection {
+    async fn query(&self, sql: String) -> Result<SqlQueryResult> {
+        let mut statement = self.prepare(&sql)?;
+        let columns = statement
+            .column_names()
+            .into_iter()
+            .map(|name| SqlColumn {
+                name: name.to_owned(),
+                data_type: None,
+            })
+            .collect::<Vec<_>>();
+        let rows = statement
+            .query_map([], |row| {
+                (0..columns.len())
+                    .map(|index| row.get_ref(index).map(json_value))
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(SqlQueryResult { columns, rows })
+    }
+}
+
+// Converts a value to JSON in the same way as values of cloud databases
+fn json_value(value: ValueRef<'_>) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => f.into(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into_owned().into(),
+        ValueRef::Blob(b) => b.to_vec().into(),
+    }
+}
+
+/// Replaces a local database with a copy of a cloud database. The copy is
+/// written next to the local database and moved over it only once complete,
+/// so that a failed pull leaves the local database as it was.
+pub(super) async fn pull(
+    client: &impl CloudClientInterface,
+    database: &str,
+    path: &Path,
+    force: bool,
+) -> Result<()> {
+    if path.exists() && !force {
+        bail!(
+            "{} already exists. Use --force to replace it.",
+            path.display()
+        );
+    }
+    let dump = dump::export(&CloudDatabase { client, database }).await?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Could not create directory {}", dir.display()))?;
+    let copy = tempfile::Builder::new()
+        .prefix(".pull-")
+        .tempfile_in(dir)
+        .with_context(|| format!("Could not create a file in {}", dir.display()))?;
+    Connection::open(copy.path())
+        .and_then(|connection| connection.execute_batch(&dump))
+        .with_context(|| format!("Could not write the database to {}", copy.path().display()))?;
+
+    // The journal files of the replaced database would corrupt the copy
+    for suffix in ["-wal", "-shm"] {
+        let mut journal = path.as_os_str().to_owned();
+        journal.push(suffix);
+        match std::fs::remove_file(&journal) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e)
+                    .with_context(|| format!("Could not remove {}", Path::new(&journal).display()))
+            }
+            _ => {}
+        }
+    }
+    copy.persist(path)
+        .with_context(|| format!("Could not replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Replaces the contents of a cloud database with a copy of a local database
+pub(super) async fn push(
+    client: &impl CloudClientInterface,
+    database: &str,
+    path: &Path,
+    force: bool,
+    batch_size: usize,
+) -> Result<()> {
+    if !path.exists() {
+        bail!("No local database found at {}", path.display());
+    }
+    let dump = {
+        let connection =
+            Connection::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+        dump::export(&connection).await?
+    };
+
+    let existing = client
+        .execute_sql(
+            database.to_owned(),
+            "SELECT type, name FROM sqlite_master WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%'".to_owned(),
+        )
+        .await
+        .context("Problem reading the database schema")?;
+    if !existing.rows.is_empty() {
+        if !force {
+            bail!("Database \"{database}\" is not empty. Use --force to replace its contents.");
+        }
+        let drops = existing
+            .rows
+            .iter()
+            .map(|row| {
+                let text =
+                    |index: usize| row.get(index).and_then(|v| v.as_str()).unwrap_or_default();
+                format!(
+                    "DROP {} IF EXISTS {};",
+                    text(0).to_uppercase(),
+                    quote_identifier(text(1))
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        client
+            .execute_sql(
+                database.to_owned(),
+                format!("PRAGMA foreign_keys=OFF;\n{drops}"),
+            )
+            .await
+            .context("Problem removing the existing contents of the database")?;
+    }
+
+    dump::import(client, database, &dump, batch_size).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod local_tests {
+    use super::*;
+    use cloud::MockCloudClientInterface;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_pull_writes_cloud_database_to_file() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s.contains("FROM sqlite_master"))
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![vec![
+                        json!("table"),
+                        json!("notes"),
+                        json!("CREATE TABLE notes (id INTEGER, body BLOB)"),
+                    ]],
+                    ..Default::default()
+                })
+            });
+        mock.expect_execute_sql()
+            .withf(|_, s| s.starts_with("SELECT _rowid_, * FROM \"notes\""))
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![vec![json!(1), json!(1), json!([1, 2])]],
+                    ..Default::default()
+                })
+            });
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(".spin").join("sqlite_db.db");
+        pull(&mock, "db1", &path, false).await?;
+
+        let connection = Connection::open(&path)?;
+        let result = connection
+            .query("SELECT id, body FROM notes".to_owned())
+            .await?;
+        assert_eq!(vec![vec![json!(1), json!([1, 2])]], result.rows);
+
+        pull(&mock, "db1", &path, false)
+            .await
+            .expect_err("should not have replaced the file without --force");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_pull_keeps_the_local_database() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("local.db");
+        Connection::open(&path)?.execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);")?;
+        let wal = dir.path().join("local.db-wal");
+        std::fs::write(&wal, "stale")?;
+
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql()
+            .times(1)
+            .returning(|_, _| anyhow::bail!("database not found"));
+        pull(&mock, "db1", &path, true)
+            .await
+            .expect_err("pull should have failed");
+        assert!(wal.exists());
+        std::fs::remove_file(&wal)?;
+        let result = Connection::open(&path)?
+            .query("SELECT x FROM t".to_owned())
+            .await?;
+        assert_eq!(vec![vec![json!(1)]], result.rows);
+        std::fs::write(&wal, "stale")?;
+
+        mock.checkpoint();
+        mock.expect_execute_sql()
+            .returning(|_, _| Ok(Default::default()));
+        pull(&mock, "db1", &path, true).await?;
+        assert!(!wal.exists());
+        let result = Connection::open(&path)?
+            .query("SELECT name FROM sqlite_master".to_owned())
+            .await?;
+        assert!(result.rows.is_empty());
+        assert_eq!(1, std::fs::read_dir(dir.path())?.count());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_push_requires_force_to_replace_contents() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("local.db");
+        Connection::open(&path)?.execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);")?;
+
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s.starts_with("SELECT type, name"))
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![vec![json!("table"), json!("old")]],
+                    ..Default::default()
+                })
+            });
+        push(&mock, "db1", &path, false, 100)
+            .await
+            .expect_err("should not have replaced the contents without --force");
+
+        mock.expect_execute_sql()
+            .withf(|_, s| s == "PRAGMA foreign_keys=OFF;\nDROP TABLE IF EXISTS \"old\";")
+            .times(1)
+            .returning(|_, _| Ok(Default::default()));
+        mock.expect_execute_sql()
+            .withf(|_, s| s.contains("CREATE TABLE t (x);\nINSERT INTO \"t\" VALUES(1);"))
+            .times(1)
+            .returning(|_, _| Ok(Default::default()));
+        push(&mock, "db1", &path, true, 100).await
+    }
+}
@@ -0,0 +1,451 @@
+//! Versioned migrations of cloud databases. Migrations are SQL files named
+//! after their version, such as `0001_create_users.sql`, and are recorded in
+//! a table in the database when applied.
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use cloud::CloudClientInterface;
+use comfy_table::presets::ASCII_BORDERS_ONLY_CONDENSED;
+use sha2::{Digest, Sha256};
+
+use super::dump::is_transaction_statement;
+use super::statements::split_statements;
+use super::transaction::rollback;
+
+const MIGRATIONS_TABLE: &str = "_spin_cloud_migrations";
+
+/// A migration file
+#[derive(Debug)]
+pub(super) struct Migration {
+    version: u64,
+    name: String,
+    sql: String,
+    checksum: String,
+}
+
+/// A migration recorded in the database as applied
+#[derive(Debug)]
+struct AppliedMigration {
+    version: u64,
+    name: String,
+    checksum: String,
+    applied_at: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum MigrationStatus {
+    Applied {
+        applied_at: String,
+    },
+    Pending,
+    /// The file has changed since the migration was applied
+    Changed,
+    /// The migration was applied, but there is no longer a file for it
+    Missing,
+}
+
+/// Reads the migration files in a directory, ordered by version
+pub(super) fn read_migrations(dir: &Path) -> Result<Vec<Migration>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Could not read migrations directory {}", dir.display()))?;
+    let mut migrations = BTreeMap::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("sql")) {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let digits = name
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>();
+        let Ok(version) = digits.parse::<u64>() else {
+            bail!(
+                "Migration file '{name}' must start with its version number, such as '0001_{name}'"
+            );
+        };
+        let sql = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read migration file {}", path.display()))?;
+        // Each migration runs in a transaction of its own, which statements
+        // in the file would end early or fail to start
+        if let Some(statement) = split_statements(&sql)
+            .into_iter()
+            .find(|s| is_transaction_statement(&s.text))
+        {
+            bail!(
+                "Migration file '{name}' must not control transactions, but line {} is: {}",
+                statement.line,
+                statement.text
+            );
+        }
+        let migration = Migration {
+            version,
+            checksum: checksum(&sql),
+            name,
+            sql,
+        };
+        if let Some(other) = migrations.insert(version, migration) {
+            bail!(
+                "Migration files '{}' and '{}' have the same version {version}",
+                other.name,
+                migrations[&version].name
+            );
+        }
+    }
+    Ok(migrations.into_values().collect())
+}
+
+fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+async fn applied_migrations(
+    client: &impl CloudClientInterface,
+    database: &str,
+) -> Result<Vec<AppliedMigration>> {
+    let tables = client
+        .execute_sql(
+            database.to_owned(),
+            format!("SELECT name FROM sqlite_master WHERE type = 'table' AND name = '{MIGRATIONS_TABLE}'"),
+        )
+        .await
+        .context("Problem reading applied migrations")?;
+    if tables.rows.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let result = client
+        .execute_sql(
+            database.to_owned(),
+            format!("SELECT version, name, checksum, applied_at FROM {MIGRATIONS_TABLE} ORDER BY version"),
+        )
+        .await
+        .context("Problem reading applied migrations")?;
+    result
+        .rows
+        .iter()
+        .map(|row| {
+            let text = |index: usize| {
+                row.get(index)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_owned()
+            };
+            Ok(AppliedMigration {
+                version: row
+                    .first()
+                    .and_then(|v| v.as_u64())
+                    .context("Invalid version in the migrations table")?,
+                name: text(1),
+                checksum: text(2),
+                applied_at: text(3),
+            })
+        })
+        .collect()
+}
+
+/// The status of every migration that either has a file or has been applied,
+/// ordered by version
+fn statuses<'a>(
+    migrations: &'a [Migration],
+    applied: &'a [AppliedMigration],
+) -> Vec<(u64, &'a str, MigrationStatus)> {
+    let mut statuses = BTreeMap::new();
+    for migration in migrations {
+        statuses.insert(
+            migration.version,
+            (migration.name.as_str(), MigrationStatus::Pending),
+        );
+    }
+    for applied in applied {
+        let status = match migrations.iter().find(|m| m.version == applied.version) {
+            None => MigrationStatus::Missing,
+            Some(m) if m.checksum != applied.checksum => MigrationStatus::Changed,
+            Some(_) => MigrationStatus::Applied {
+                applied_at: applied.applied_at.clone(),
+            },
+        };
+        statuses.insert(applied.version, (applied.name.as_str(), status));
+    }
+    statuses
+        .into_iter()
+        .map(|(version, (name, status))| (version, name, status))
+        .collect()
+}
+
+/// Finds the migrations to apply, checking that the applied migrations still
+/// match their files
+fn pending_migrations<'a>(
+    migrations: &'a [Migration],
+    applied: &[AppliedMigration],
+) -> Result<Vec<&'a Migration>> {
+    let statuses = statuses(migrations, applied);
+    for (version, name, status) in &statuses {
+        match status {
+            MigrationStatus::Changed => bail!(
+                "Migration {version} ('{name}') has changed since it was applied. Applied migrations must not be edited; add a new migration instead."
+            ),
+            MigrationStatus::Missing => {
+                eprintln!("Warning: migration {version} ('{name}') was applied but its file no longer exists")
+            }
+            _ => {}
+        }
+    }
+
+    let latest_applied = applied.iter().map(|a| a.version).max();
+    let pending = migrations
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+        .collect::<Vec<_>>();
+    if let (Some(latest), Some(first)) = (latest_applied, pending.first()) {
+        if first.version < latest {
+            bail!(
+                "Migration {} ('{}') is older than the latest applied migration {latest}. Give it a later version to apply it.",
+                first.version,
+                first.name
+            );
+        }
+    }
+    Ok(pending)
+}
+
+// A migration and its record are applied in one transaction, so that a
+// failed migration leaves nothing behind
+fn migration_script(migration: &Migration) -> String {
+    format!(
+        "BEGIN;\n{}\n;\nINSERT INTO {MIGRATIONS_TABLE} (version, name, checksum, applied_at) VALUES ({}, '{}', '{}', datetime('now'));\nCOMMIT;",
+        migration.sql,
+        migration.version,
+        migration.name.replace('\'', "''"),
+        migration.checksum
+    )
+}
+
+pub(super) async fn print_status(
+    client: &impl CloudClientInterface,
+    database: &str,
+    migrations: &[Migration],
+) -> Result<()> {
+    let applied = applied_migrations(client, database).await?;
+    let statuses = statuses(migrations, &applied);
+    if statuses.is_empty() {
+        println!("No migrations found");
+        return Ok(());
+    }
+
+    let mut table = comfy_table::Table::new();
+    table.load_preset(ASCII_BORDERS_ONLY_CONDENSED);
+    table.set_header(vec!["Version", "Name", "Status"]);
+    table.add_rows(statuses.into_iter().map(|(version, name, status)| {
+        let status = match status {
+            MigrationStatus::Applied { applied_at } => format!("applied {applied_at}"),
+            MigrationStatus::Pending => "pending".to_owned(),
+            MigrationStatus::Changed => "changed since applied".to_owned(),
+            MigrationStatus::Missing => "applied, file missing".to_owned(),
+        };
+        vec![version.to_string(), name.to_owned(), status]
+    }));
+    println!("{table}");
+    Ok(())
+}
+
+pub(super) async fn migrate(
+    client: &impl CloudClientInterface,
+    database: &str,
+    migrations: &[Migration],
+    dry_run: bool,
+) -> Result<()> {
+    let applied = applied_migrations(client, database).await?;
+    let pending = pending_migrations(migrations, &applied)?;
+    if pending.is_empty() {
+        println!("Database \"{database}\" is up to date");
+        return Ok(());
+    }
+    if dry_run {
+        println!("Would apply {} migrations:", pending.len());
+        for migration in pending {
+            println!("  {} ({})", migration.version, migration.name);
+        }
+        return Ok(());
+    }
+
+    client
+        .execute_sql(
+            database.to_owned(),
+            format!("CREATE TABLE IF NOT EXISTS {MIGRATIONS_TABLE} (version INTEGER PRIMARY KEY, name TEXT NOT NULL, checksum TEXT NOT NULL, applied_at TEXT NOT NULL)"),
+        )
+        .await
+        .context("Problem creating the migrations table")?;
+    for migration in pending {
+        println!(
+            "Applying migration {} ({})",
+            migration.version, migration.name
+        );
+        if let Err(e) = client
+            .execute_sql(database.to_owned(), migration_script(migration))
+            .await
+        {
+            rollback(client, database).await;
+            return Err(e)
+                .with_context(|| format!("Problem applying migration '{}'", migration.name));
+        }
+    }
+    println!("Database \"{database}\" is up to date");
+    Ok(())
+}
+
+#[cfg(test)]
+mod migrate_tests {
+    use super::*;
+    use cloud::models::SqlQueryResult;
+    use cloud::MockCloudClientInterface;
+    use serde_json::json;
+
+    fn migrations_dir(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, sql) in files {
+            std::fs::write(dir.path().join(name), sql).unwrap();
+        }
+        dir
+    }
+
+    fn applied(version: u64, name: &str, sql: &str) -> AppliedMigration {
+        AppliedMigration {
+            version,
+            name: name.to_owned(),
+            checksum: checksum(sql),
+            applied_at: "2024-01-01 00:00:00".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_migrations_are_read_in_version_order() {
+        let dir = migrations_dir(&[
+            ("10_c.sql", "C"),
+            ("2_b.sql", "B"),
+            ("0001_a.sql", "A"),
+            ("README.md", "not a migration"),
+        ]);
+        let versions = read_migrations(dir.path())
+            .unwrap()
+            .iter()
+            .map(|m| m.version)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 10], versions);
+
+        let dir = migrations_dir(&[("1_a.sql", "A"), ("001_b.sql", "B")]);
+        read_migrations(dir.path()).expect_err("should have rejected duplicate versions");
+        let dir = migrations_dir(&[("a.sql", "A")]);
+        read_migrations(dir.path()).expect_err("should have rejected a file without a version");
+        let dir = migrations_dir(&[("1_a.sql", "BEGIN;\nCREATE TABLE a (x);\nCOMMIT;")]);
+        let error = read_migrations(dir.path())
+            .expect_err("should have rejected a file with transaction statements")
+            .to_string();
+        assert!(error.contains("line 1"), "{error}");
+    }
+
+    #[test]
+    fn test_only_unapplied_migrations_are_pending() {
+        let dir = migrations_dir(&[("1_a.sql", "A"), ("2_b.sql", "B"), ("3_c.sql", "C")]);
+        let migrations = read_migrations(dir.path()).unwrap();
+
+        let pending = pending_migrations(&migrations, &[applied(1, "1_a.sql", "A")]).unwrap();
+        assert_eq!(
+            vec![2, 3],
+            pending.iter().map(|m| m.version).collect::<Vec<_>>()
+        );
+
+        pending_migrations(&migrations, &[applied(1, "1_a.sql", "edited")])
+            .expect_err("should have rejected a changed migration");
+        pending_migrations(&migrations, &[applied(2, "2_b.sql", "B")])
+            .expect_err("should have rejected a migration older than the applied ones");
+    }
+
+    #[tokio::test]
+    async fn test_migrate_applies_pending_migrations_with_their_record() -> Result<()> {
+        let dir = migrations_dir(&[
+            ("1_a.sql", "CREATE TABLE a (x)"),
+            ("2_b.sql", "CREATE TABLE b (x)"),
+        ]);
+        let migrations = read_migrations(dir.path())?;
+
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s.contains("FROM sqlite_master"))
+            .returning(|_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![vec![json!(MIGRATIONS_TABLE)]],
+                    ..Default::default()
+                })
+            });
+        let applied_sql = "CREATE TABLE a (x)".to_owned();
+        mock.expect_execute_sql()
+            .withf(|_, s| s.starts_with("SELECT version"))
+            .returning(move |_, _| {
+                Ok(SqlQueryResult {
+                    rows: vec![vec![
+                        json!(1),
+                        json!("1_a.sql"),
+                        json!(checksum(&applied_sql)),
+                        json!("2024-01-01 00:00:00"),
+                    ]],
+                    ..Default::default()
+                })
+            });
+        mock.expect_execute_sql()
+            .withf(|_, s| s.starts_with("CREATE TABLE IF NOT EXISTS"))
+            .times(1)
+            .returning(|_, _| Ok(Default::default()));
+        mock.expect_execute_sql()
+            .withf(|db, s| {
+                db == "db1"
+                    && s.starts_with("BEGIN;\nCREATE TABLE b (x)")
+                    && s.contains("VALUES (2, '2_b.sql'")
+                    && s.ends_with("COMMIT;")
+            })
+            .times(1)
+            .returning(|_, _| Ok(Default::default()));
+
+        migrate(&mock, "db1", &migrations, false).await
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_is_rolled_back() -> Result<()> {
+        let dir = migrations_dir(&[("1_a.sql", "CREATE TABLE a (x")]);
+        let migrations = read_migrations(dir.path())?;
+
+        let mut mock = MockCloudClientInterface::new();
+        let mut seq = mockall::Sequence::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s.contains("FROM sqlite_master"))
+            .returning(|_, _| Ok(Default::default()));
+        mock.expect_execute_sql()
+            .withf(|_, s| s.starts_with("CREATE TABLE IF NOT EXISTS"))
+            .returning(|_, _| Ok(Default::default()));
+        mock.expect_execute_sql()
+            .withf(|_, s| s.starts_with("BEGIN;"))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| anyhow::bail!("incomplete input"));
+        mock.expect_execute_sql()
+            .withf(|_, s| s == "ROLLBACK;")
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(Default::default()));
+
+        let error = migrate(&mock, "db1", &migrations, false)
+            .await
+            .expect_err("migration should have failed");
+        assert_eq!("Problem applying migration '1_a.sql'", error.to_string());
+        Ok(())
+    }
+}
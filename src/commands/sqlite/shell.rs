@@ -0,0 +1,212 @@
+//! An interactive shell for running SQL against a cloud database
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use cloud::CloudClientInterface;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use super::statements::{is_complete, split_statements};
+use crate::commands::sqlite_output::{print_query_result, QueryFormat};
+
+const HELP: &str = "\
+Enter SQL statements terminated with a \";\". Statements can span several lines.
+
+.help              Show this message
+.mode table|csv|json
+                   Set the output format of query results
+.quit              Exit the shell
+.schema ?TABLE?    Show the CREATE statements of all tables, or of TABLE
+.tables            List the names of the tables";
+
+/// Whether the shell keeps reading input after a command
+#[derive(Debug, PartialEq)]
+enum Flow {
+    Continue,
+    Quit,
+}
+
+pub(super) struct Shell<'a, C> {
+    client: &'a C,
+    database: String,
+    format: QueryFormat,
+}
+
+impl<'a, C: CloudClientInterface> Shell<'a, C> {
+    pub(super) fn new(client: &'a C, database: String, format: QueryFormat) -> Self {
+        Self {
+            client,
+            database,
+            format,
+        }
+    }
+
+    /// Reads and runs input until the user quits
+    pub(super) async fn run(&mut self) -> Result<()> {
+        let mut editor = DefaultEditor::new().context("Could not start the shell")?;
+        let history = history_path();
+        if let Some(history) = &history {
+            // There is no history the first time the shell is used
+            _ = editor.load_history(history);
+        }
+        println!(
+            "Connected to database \"{}\". Enter \".help\" for usage hints.",
+            self.database
+        );
+
+        let mut buffer = String::new();
+        loop {
+            let prompt = if buffer.is_empty() {
+                "sqlite> "
+            } else {
+                "   ...> "
+            };
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                // Ctrl-C discards the statement being entered
+                Err(ReadlineError::Interrupted) => {
+                    buffer.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e).context("Could not read input"),
+            };
+
+            if buffer.is_empty() && line.trim_start().starts_with('.') {
+                _ = editor.add_history_entry(line.as_str());
+                match self.dot_command(line.trim()).await {
+                    Ok(Flow::Continue) => {}
+                    Ok(Flow::Quit) => break,
+                    Err(e) => eprintln!("Error: {e:#}"),
+                }
+                continue;
+            }
+
+            if !buffer.is_empty() {
+                buffer.push('\n');
+            }
+            buffer.push_str(&line);
+            if is_complete(&buffer) {
+                let sql = std::mem::take(&mut buffer);
+                if !sql.trim().is_empty() {
+                    _ = editor.add_history_entry(sql.as_str());
+                }
+                if let Err(e) = self.execute(&sql).await {
+                    eprintln!("Error: {e:#}");
+                }
+            }
+        }
+
+        if let Some(history) = &history {
+            if let Some(dir) = history.parent() {
+                _ = std::fs::create_dir_all(dir);
+            }
+            if let Err(e) = editor.save_history(history) {
+                tracing::warn!("Could not save shell history: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs each statement in turn, stopping at the first that fails
+    async fn execute(&self, sql: &str) -> Result<()> {
+        for statement in split_statements(sql) {
+            let result = self
+                .client
+                .execute_sql(self.database.clone(), statement.text)
+                .await?;
+            print_query_result(&result, self.format)?;
+        }
+        Ok(())
+    }
+
+    async fn dot_command(&mut self, command: &str) -> Result<Flow> {
+        let mut args = command.split_whitespace();
+        match (args.next().unwrap_or_default(), args.next()) {
+            (".quit" | ".exit", None) => return Ok(Flow::Quit),
+            (".help", None) => println!("{HELP}"),
+            (".tables", None) => {
+                self.execute(
+                    "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;",
+                )
+                .await?
+            }
+            (".schema", table) => {
+                let filter = match table {
+                    Some(table) => format!(" AND tbl_name = '{}'", table.replace('\'', "''")),
+                    None => String::new(),
+                };
+                let result = self
+                    .client
+                    .execute_sql(
+                        self.database.clone(),
+                        format!("SELECT sql FROM sqlite_master WHERE sql IS NOT NULL{filter} ORDER BY tbl_name, type DESC, name"),
+                    )
+                    .await?;
+                for row in result.rows {
+                    if let Some(sql) = row.first().and_then(|v| v.as_str()) {
+                        println!("{sql};");
+                    }
+                }
+            }
+            (".mode", Some(mode)) => {
+                self.format = match mode {
+                    "table" => QueryFormat::Table,
+                    "csv" => QueryFormat::Csv,
+                    "json" => QueryFormat::Json,
+                    _ => bail!(r#"Unknown mode "{mode}". Use "table", "csv" or "json"."#),
+                }
+            }
+            _ => bail!(r#"Unknown command or invalid arguments: "{command}". Enter ".help" for help."#),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("fermyon").join("sqlite_history"))
+}
+
+#[cfg(test)]
+mod shell_tests {
+    use super::*;
+    use cloud::MockCloudClientInterface;
+
+    #[tokio::test]
+    async fn test_statements_are_executed_one_at_a_time() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        let mut seq = mockall::Sequence::new();
+        for sql in ["CREATE TABLE a (x)", "INSERT INTO a VALUES (';')"] {
+            mock.expect_execute_sql()
+                .withf(move |db, s| db == "db1" && s == sql)
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|_, _| Ok(Default::default()));
+        }
+
+        let shell = Shell::new(&mock, "db1".to_owned(), QueryFormat::Table);
+        shell
+            .execute("CREATE TABLE a (x);\nINSERT INTO a VALUES (';');")
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_dot_commands() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s.contains("FROM sqlite_master") && s.contains("tbl_name = 'it''s'"))
+            .times(1)
+            .returning(|_, _| Ok(Default::default()));
+
+        let mut shell = Shell::new(&mock, "db1".to_owned(), QueryFormat::Table);
+        assert_eq!(Flow::Continue, shell.dot_command(".schema it's").await?);
+        assert_eq!(Flow::Continue, shell.dot_command(".mode csv").await?);
+        assert_eq!(QueryFormat::Csv, shell.format);
+        shell
+            .dot_command(".mode xml")
+            .await
+            .expect_err("should not have accepted xml mode");
+        assert_eq!(Flow::Quit, shell.dot_command(".quit").await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! Splitting of SQL scripts into statements. Semicolons only end a statement
+//! outside of quotes, comments and the body of a trigger.
+
+/// A statement in a script, and the line it starts on
+#[derive(Debug, PartialEq)]
+pub(crate) struct Statement {
+    pub text: String,
+    pub line: usize,
+}
+
+/// Splits SQL into statements, without their terminating semicolons. A last
+/// statement without a semicolon is included too.
+pub(crate) fn split_statements(sql: &str) -> Vec<Statement> {
+    scan(sql).0
+}
+
+/// Whether all statements in the SQL are terminated, so that nothing is left
+/// in an unfinished statement, quote or comment
+pub(crate) fn is_complete(sql: &str) -> bool {
+    scan(sql).1
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Normal,
+    Quoted(char),
+    LineComment,
+    BlockComment,
+}
+
+fn scan(sql: &str) -> (Vec<Statement>, bool) {
+    let mut statements = vec![];
+    let mut state = State::Normal;
+    let mut line = 1;
+    // Where the current statement starts, as a byte offset and a line
+    let mut start: Option<(usize, usize)> = None;
+    let mut words = Words::default();
+
+    let mut chars = sql.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match state {
+            State::Normal => {
+                if c.is_alphanumeric() || c == '_' {
+                    words.current.push(c);
+                } else {
+                    words.end_word();
+                }
+                let next = chars.peek().map(|(_, c)| *c);
+                match c {
+                    '-' if next == Some('-') => {
+                        chars.next();
+                        state = State::LineComment;
+                        continue;
+                    }
+                    '/' if next == Some('*') => {
+                        chars.next();
+                        state = State::BlockComment;
+                        continue;
+                    }
+                    ';' if !words.in_trigger_body() => {
+                        if let Some((offset, line)) = start.take() {
+                            statements.push(Statement {
+                                text: sql[offset..index].trim_end().to_owned(),
+                                line,
+                            });
+                        }
+                        words = Words::default();
+                        continue;
+                    }
+                    '\'' | '"' | '`' => state = State::Quoted(c),
+                    '[' => state = State::Quoted(']'),
+                    _ => {}
+                }
+                if start.is_none() && !c.is_whitespace() {
+                    start = Some((index, line));
+                }
+            }
+            State::Quoted(quote) if c == quote => {
+                // Quotes are escaped by doubling them
+                if quote != ']' && chars.peek().map(|(_, c)| *c) == Some(quote) {
+                    chars.next();
+                } else {
+                    state = State::Normal;
+                }
+            }
+            State::LineComment if c == '\n' => state = State::Normal,
+            State::BlockComment if c == '*' && chars.peek().map(|(_, c)| *c) == Some('/') => {
+                chars.next();
+                state = State::Normal;
+            }
+            _ => {}
+        }
+        if c == '\n' {
+            line += 1;
+        }
+    }
+
+    let complete = start.is_none() && matches!(state, State::Normal | State::LineComment);
+    if let Some((offset, line)) = start {
+        statements.push(Statement {
+            text: sql[offset..].trim_end().to_owned(),
+            line,
+        });
+    }
+    (statements, complete)
+}
+
+/// The words of a statement that tell whether a semicolon ends it
+#[derive(Default)]
+struct Words {
+    current: String,
+    /// The first words of the statement, uppercased
+    leading: Vec<String>,
+    last: String,
+}
+
+impl Words {
+    fn end_word(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        let word = std::mem::take(&mut self.current).to_uppercase();
+        if self.leading.len() < 3 {
+            self.leading.push(word.clone());
+        }
+        self.last = word;
+    }
+
+    // Triggers contain statements of their own, so they only end at the
+    // semicolon after `END`
+    fn in_trigger_body(&self) -> bool {
+        let is_trigger = match self.leading.as_slice() {
+            [create, trigger, ..] if create == "CREATE" && trigger == "TRIGGER" => true,
+            [create, temp, trigger]
+                if create == "CREATE"
+                    && (temp == "TEMP" || temp == "TEMPORARY")
+                    && trigger == "TRIGGER" =>
+            {
+                true
+            }
+            _ => false,
+        };
+        is_trigger && self.last != "END"
+    }
+}
+
+#[cfg(test)]
+mod statements_tests {
+    use super::*;
+
+    fn texts(sql: &str) -> Vec<String> {
+        split_statements(sql).into_iter().map(|s| s.text).collect()
+    }
+
+    #[test]
+    fn test_semicolons_in_quotes_and_comments_do_not_split() {
+        assert_eq!(
+            vec![
+                "INSERT INTO t VALUES ('a;b', \"c;d\", [e;f])",
+                "SELECT 'it''s; fine' /* ; */ -- ;\nFROM t"
+            ],
+            texts(
+                "INSERT INTO t VALUES ('a;b', \"c;d\", [e;f]);\nSELECT 'it''s; fine' /* ; */ -- ;\nFROM t;"
+            )
+        );
+    }
+
+    #[test]
+    fn test_statements_record_their_line() {
+        let statements = split_statements("-- setup\nCREATE TABLE a (x);\n\n\nSELECT *\nFROM a");
+        assert_eq!(
+            vec![
+                Statement {
+                    text: "CREATE TABLE a (x)".to_owned(),
+                    line: 2
+                },
+                Statement {
+                    text: "SELECT *\nFROM a".to_owned(),
+                    line: 5
+                },
+            ],
+            statements
+        );
+    }
+
+    #[test]
+    fn test_trigger_bodies_are_not_split() {
+        assert_eq!(
+            vec![
+                "CREATE TEMP TRIGGER t AFTER INSERT ON a BEGIN INSERT INTO b VALUES (1); END",
+                "SELECT 1"
+            ],
+            texts("CREATE TEMP TRIGGER t AFTER INSERT ON a BEGIN INSERT INTO b VALUES (1); END; SELECT 1;")
+        );
+    }
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete(""));
+        assert!(is_complete("SELECT 1; -- done"));
+        assert!(!is_complete("SELECT 1"));
+        assert!(!is_complete("SELECT 'a;"));
+        assert!(!is_complete("SELECT 1; /* unfinished"));
+        assert!(!is_complete(
+            "CREATE TRIGGER t AFTER INSERT ON a BEGIN SELECT 1;"
+        ));
+    }
+}
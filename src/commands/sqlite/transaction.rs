@@ -0,0 +1,166 @@
+//! Running a script as a single transaction, so that a failing statement
+//! leaves the database as it was before the script
+use anyhow::{Context, Result};
+use cloud::models::SqlQueryResult;
+use cloud::CloudClientInterface;
+
+use super::dump::is_transaction_statement;
+use super::statements::{split_statements, Statement};
+
+/// Runs the statements of the SQL between BEGIN and COMMIT in one request.
+/// If that fails, the statement that caused the failure is found by bisecting
+/// the script, running each prefix tried in a transaction that is rolled back.
+pub(super) async fn execute_in_transaction(
+    client: &impl CloudClientInterface,
+    database: &str,
+    sql: &str,
+) -> Result<SqlQueryResult> {
+    // The script supplies its own transaction, so any in the SQL are dropped
+    let statements = split_statements(sql)
+        .into_iter()
+        .filter(|s| !is_transaction_statement(&s.text))
+        .collect::<Vec<_>>();
+
+    let error = match client
+        .execute_sql(database.to_owned(), script(&statements, "COMMIT"))
+        .await
+    {
+        Ok(result) => return Ok(result),
+        Err(e) => e,
+    };
+    rollback(client, database).await;
+
+    match find_failing_statement(client, database, &statements).await? {
+        Some((statement, error)) => Err(error).with_context(|| {
+            format!(
+                "Statement on line {} failed and the transaction was rolled back: {}",
+                statement.line, statement.text
+            )
+        }),
+        None => Err(error).context("Problem committing the transaction"),
+    }
+}
+
+/// Finds the first statement that fails when run after the statements before
+/// it, together with the error it caused
+async fn find_failing_statement<'a>(
+    client: &impl CloudClientInterface,
+    database: &str,
+    statements: &'a [Statement],
+) -> Result<Option<(&'a Statement, anyhow::Error)>> {
+    let Err(mut error) = probe(client, database, statements).await else {
+        // Every statement succeeded, so it was the commit that failed
+        return Ok(None);
+    };
+    // The first `good` statements are known to succeed, and the first `bad`
+    // statements to fail
+    let (mut good, mut bad) = (0, statements.len());
+    while bad - good > 1 {
+        let middle = good + (bad - good) / 2;
+        match probe(client, database, &statements[..middle]).await {
+            Ok(()) => good = middle,
+            Err(e) => (bad, error) = (middle, e),
+        }
+    }
+    Ok(Some((&statements[bad - 1], error)))
+}
+
+/// Runs statements in a transaction that is rolled back whether or not they
+/// succeed
+async fn probe(
+    client: &impl CloudClientInterface,
+    database: &str,
+    statements: &[Statement],
+) -> Result<()> {
+    let result = client
+        .execute_sql(database.to_owned(), script(statements, "ROLLBACK"))
+        .await;
+    if result.is_err() {
+        rollback(client, database).await;
+    }
+    result.map(|_| ())
+}
+
+fn script(statements: &[Statement], end: &str) -> String {
+    let mut script = String::from("BEGIN;\n");
+    for statement in statements {
+        script.push_str(&statement.text);
+        script.push_str(";\n");
+    }
+    script.push_str(end);
+    script.push(';');
+    script
+}
+
+// A failed request may leave its transaction open. If it does not, rolling
+// back fails harmlessly because no transaction is active.
+pub(super) async fn rollback(client: &impl CloudClientInterface, database: &str) {
+    if let Err(e) = client
+        .execute_sql(database.to_owned(), "ROLLBACK;".to_owned())
+        .await
+    {
+        tracing::debug!("Rollback after failed statement: {e:#}");
+    }
+}
+
+#[cfg(test)]
+mod transaction_tests {
+    use super::*;
+    use cloud::MockCloudClientInterface;
+
+    #[tokio::test]
+    async fn test_transaction_is_committed_in_one_request() -> Result<()> {
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql()
+            .withf(|_, s| s == "BEGIN;\nCREATE TABLE t (x);\nINSERT INTO t VALUES (1);\nCOMMIT;")
+            .times(1)
+            .returning(|_, _| Ok(Default::default()));
+
+        execute_in_transaction(
+            &mock,
+            "db1",
+            "BEGIN;\nCREATE TABLE t (x);\nINSERT INTO t VALUES (1);\nCOMMIT;",
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failing_statement_is_reported_with_its_line() {
+        let mut mock = MockCloudClientInterface::new();
+        // Any script that includes the third statement fails
+        mock.expect_execute_sql().returning(|_, s| {
+            if s.contains("INSERT INTO missing") {
+                anyhow::bail!("no such table: missing")
+            }
+            Ok(Default::default())
+        });
+
+        let sql = "CREATE TABLE a (x);\nINSERT INTO a VALUES (1);\n\nINSERT INTO missing VALUES (1);\nINSERT INTO a VALUES (2);\n";
+        let error = execute_in_transaction(&mock, "db1", sql)
+            .await
+            .expect_err("transaction should have failed");
+        let message = format!("{error:#}");
+        assert!(
+            message.starts_with("Statement on line 4 failed"),
+            "{message}"
+        );
+        assert!(message.ends_with("no such table: missing"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn test_commit_failure_is_reported_when_all_statements_succeed() {
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_execute_sql().returning(|_, s| {
+            if s.ends_with("COMMIT;") && s.len() > "COMMIT;".len() {
+                anyhow::bail!("FOREIGN KEY constraint failed")
+            }
+            Ok(Default::default())
+        });
+
+        let error = execute_in_transaction(&mock, "db1", "INSERT INTO a VALUES (1);")
+            .await
+            .expect_err("transaction should have failed");
+        assert_eq!("Problem committing the transaction", error.to_string());
+    }
+}
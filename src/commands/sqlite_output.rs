@@ -0,0 +1,140 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use cloud::models::{SqlColumn, SqlQueryResult};
+use comfy_table::presets::ASCII_BORDERS_ONLY_CONDENSED;
+use serde::Serialize;
+
+#[derive(Debug, ValueEnum, PartialEq, Clone, Copy)]
+pub enum QueryFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+pub(crate) fn print_query_result(result: &SqlQueryResult, format: QueryFormat) -> Result<()> {
+    // Statements that return nothing, such as inserts, print nothing unless
+    // the output is meant for other tools
+    if result.columns.is_empty() && format != QueryFormat::Json {
+        return Ok(());
+    }
+    print!("{}", format_query_result(result, format)?);
+    Ok(())
+}
+
+pub(crate) fn format_query_result(result: &SqlQueryResult, format: QueryFormat) -> Result<String> {
+    let formatted = match format {
+        QueryFormat::Table => {
+            let mut table = comfy_table::Table::new();
+            table.load_preset(ASCII_BORDERS_ONLY_CONDENSED);
+            table.set_header(result.columns.iter().map(column_header));
+            table.add_rows(
+                result
+                    .rows
+                    .iter()
+                    .map(|row| row.iter().map(display_value).collect::<Vec<_>>()),
+            );
+            format!("{table}\n")
+        }
+        QueryFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(result.columns.iter().map(|c| &c.name))?;
+            for row in &result.rows {
+                // NULLs are left empty, as most tools reading CSV expect
+                writer.write_record(row.iter().map(|v| match v {
+                    serde_json::Value::Null => String::new(),
+                    v => display_value(v),
+                }))?;
+            }
+            String::from_utf8(writer.into_inner()?)?
+        }
+        QueryFormat::Json => {
+            let json = QueryResultJson {
+                columns: &result.columns,
+                rows: result
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        result
+                            .columns
+                            .iter()
+                            .map(|c| c.name.clone())
+                            .zip(row.iter().cloned())
+                            .collect()
+                    })
+                    .collect(),
+            };
+            format!("{}\n", serde_json::to_string_pretty(&json)?)
+        }
+    };
+    Ok(formatted)
+}
+
+#[derive(Serialize)]
+struct QueryResultJson<'a> {
+    columns: &'a [SqlColumn],
+    rows: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+fn column_header(column: &SqlColumn) -> String {
+    match &column.data_type {
+        Some(data_type) if !data_type.is_empty() => format!("{} ({data_type})", column.name),
+        _ => column.name.clone(),
+    }
+}
+
+fn display_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "NULL".to_owned(),
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod sqlite_output_tests {
+    use super::*;
+    use serde_json::json;
+
+    fn query_result() -> SqlQueryResult {
+        SqlQueryResult {
+            columns: vec![
+                SqlColumn {
+                    name: "id".to_owned(),
+                    data_type: Some("INTEGER".to_owned()),
+                },
+                SqlColumn {
+                    name: "note".to_owned(),
+                    data_type: None,
+                },
+            ],
+            rows: vec![
+                vec![json!(1), json!("hello, world")],
+                vec![json!(2), json!(null)],
+            ],
+        }
+    }
+
+    #[test]
+    fn test_query_result_as_csv() {
+        assert_eq!(
+            "id,note\n1,\"hello, world\"\n2,\n",
+            format_query_result(&query_result(), QueryFormat::Csv).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_query_result_as_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&format_query_result(&query_result(), QueryFormat::Json).unwrap())
+                .unwrap();
+        assert_eq!(json!({"name": "id", "type": "INTEGER"}), json["columns"][0]);
+        assert_eq!(json!({"id": 2, "note": null}), json["rows"][1]);
+    }
+
+    #[test]
+    fn test_query_result_as_table_includes_types() {
+        let table = format_query_result(&query_result(), QueryFormat::Table).unwrap();
+        assert!(table.contains("id (INTEGER)"));
+        assert!(table.contains("hello, world"));
+    }
+}
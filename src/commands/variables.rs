@@ -1,16 +1,38 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
 use anyhow::{Context, Result};
 use clap::Parser;
-use cloud::{client::Client as CloudClient, CloudClientInterface};
-use serde::Deserialize;
+use cloud::CloudClientInterface;
+use comfy_table::presets::ASCII_BORDERS_ONLY_CONDENSED;
+use serde::{Deserialize, Serialize};
 use serde_json::from_str;
 use spin_common::arg_parser::parse_kv;
+use spin_locked_app::locked::{LockedMap, Variable as ManifestVariable};
 use uuid::Uuid;
 
-use crate::commands::{client_and_app_id, CommonArgs};
+use self::input::{merge_variables, read_variables_file, read_variables_stdin, VariablesFormat};
+use crate::commands::{
+    apps_output::OutputFormat,
+    client_and_app_id,
+    deploy::AppSource,
+    parallel::{run_all, MAX_CONCURRENT_REQUESTS},
+    secrets::SecretResolver,
+    CommonArgs,
+};
+
+pub(crate) mod input;
+mod sync;
+
+/// How the values of secret variables are shown
+const MASKED_VALUE: &str = "********";
 
 #[derive(Deserialize)]
 pub(crate) struct Variable {
     pub key: String,
+    /// The value of the variable, if Fermyon Cloud returns it
+    #[serde(default)]
+    pub value: Option<String>,
 }
 
 /// Manage Spin application variables
@@ -23,13 +45,80 @@ pub enum VariablesCommand {
     Delete(DeleteCommand),
     /// List all variables of an application
     List(ListCommand),
+    /// Show the value of a variable of an application
+    Get(GetCommand),
+    /// Compare the variables an application declares with those set in Fermyon Cloud
+    Diff(DiffCommand),
+    /// Make the variables of an application match a file, adding, updating
+    /// and optionally deleting variables
+    Sync(SyncCommand),
+}
+
+/// The application whose manifest declares variables
+#[derive(Parser, Debug)]
+pub struct ManifestArgs {
+    /// The application whose manifest declares the variables. This may be a
+    /// manifest (spin.toml) file, a directory containing a spin.toml file, or
+    /// a remote registry reference. If omitted, it defaults to "spin.toml".
+    #[clap(name = "from", short = 'f', long = "from")]
+    pub app_source: Option<String>,
+}
+
+impl ManifestArgs {
+    /// The variables the manifest declares, or None if no manifest was given
+    /// and there is none in the current directory
+    async fn variables(&self) -> Result<Option<LockedMap<ManifestVariable>>> {
+        let app_source = AppSource::resolve(self.app_source.as_deref(), None, None);
+        if app_source == AppSource::None {
+            return Ok(None);
+        }
+        let dir = tempfile::tempdir()?;
+        let app = app_source
+            .load(dir.path())
+            .await
+            .context("Problem loading the application manifest")?;
+        Ok(Some(app.variables))
+    }
+
+    /// The names of the variables whose values must be masked. Without a
+    /// manifest there is no telling which variables are secret, so all are.
+    async fn secret_variables(&self) -> Result<Secrets> {
+        match self.variables().await? {
+            Some(declared) => Ok(Secrets::Only(
+                declared
+                    .into_iter()
+                    .filter(|(_, v)| v.secret)
+                    .map(|(k, _)| k)
+                    .collect(),
+            )),
+            None => {
+                eprintln!("No application manifest found, so all values are masked. Use --from to give the manifest, or --show-secrets to show all values.");
+                Ok(Secrets::All)
+            }
+        }
+    }
 }
 
 #[derive(Parser, Debug)]
 pub struct SetCommand {
-    /// Variable pair to set
-    #[clap(parse(try_from_str = parse_kv))]
+    /// Variable pair to set. Values may refer to secrets kept elsewhere, as
+    /// env://NAME, file://path or vault://path#field. A value starting with
+    /// a backslash, such as \env://NAME, is set without the backslash and
+    /// is not resolved.
+    /// Each variable read from a reference is printed with the reference.
+    #[clap(parse(try_from_str = parse_kv), required_unless_present_any = ["from-file", "from-stdin"])]
     pub variables_to_set: Vec<(String, String)>,
+    /// Read variables from a file. The file may be a .env file, or a JSON or
+    /// TOML file of names to values, according to its extension. Variables
+    /// given as arguments take precedence.
+    #[clap(name = "from-file", long = "from-file", conflicts_with = "from-stdin")]
+    pub from_file: Option<PathBuf>,
+    /// Read variables from stdin, in the .env format unless --format is given
+    #[clap(name = "from-stdin", long = "from-stdin", takes_value = false)]
+    pub from_stdin: bool,
+    /// Format of the variables read from a file or stdin
+    #[clap(value_enum, long = "format")]
+    pub format: Option<VariablesFormat>,
     #[clap(flatten)]
     common: CommonArgs,
     /// Name of Spin app
@@ -37,6 +126,43 @@ pub struct SetCommand {
     pub app: String,
 }
 
+#[derive(Parser, Debug)]
+pub struct SyncCommand {
+    #[clap(flatten)]
+    common: CommonArgs,
+    /// Name of Spin app
+    #[clap(name = "app", long = "app")]
+    pub app: String,
+    /// The file of variables to sync. The file may be a .env file, or a JSON
+    /// or TOML file of names to values, according to its extension. Values
+    /// may refer to secrets, as env://NAME, file://path or vault://path#field,
+    /// unless escaped with a leading backslash.
+    #[clap(long = "from-file")]
+    pub from_file: PathBuf,
+    /// Format of the file, if it cannot be told from the extension
+    #[clap(value_enum, long = "format")]
+    pub format: Option<VariablesFormat>,
+    /// Delete variables that are set on the app but not in the file
+    #[clap(long = "prune", takes_value = false)]
+    pub prune: bool,
+    /// Skips prompt to confirm the changes
+    #[clap(short = 'y', long = "yes", takes_value = false)]
+    pub yes: bool,
+}
+
+impl SetCommand {
+    async fn variables(&self) -> Result<Vec<(String, String)>> {
+        let read = match &self.from_file {
+            Some(path) => read_variables_file(path, self.format)?,
+            None if self.from_stdin => read_variables_stdin(self.format)?,
+            None => vec![],
+        };
+        SecretResolver::default()
+            .resolve_variables(merge_variables([read, self.variables_to_set.clone()]))
+            .await
+    }
+}
+
 #[derive(Parser, Debug)]
 pub struct DeleteCommand {
     /// Variable pair to set
@@ -55,15 +181,58 @@ pub struct ListCommand {
     /// Name of Spin app
     #[clap(name = "app", long = "app")]
     pub app: String,
+    /// Show the values of the variables. Values of variables that the app
+    /// manifest marks as secret are masked.
+    #[clap(name = "show-values", long = "show-values", takes_value = false)]
+    pub show_values: bool,
+    /// Show the values of secret variables too
+    #[clap(long = "show-secrets", takes_value = false, requires = "show-values")]
+    pub show_secrets: bool,
+    /// Desired output format
+    #[clap(value_enum, long = "format", default_value = "plain")]
+    pub format: OutputFormat,
+    #[clap(flatten)]
+    pub manifest: ManifestArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct GetCommand {
+    /// Name of the variable
+    pub name: String,
+    #[clap(flatten)]
+    common: CommonArgs,
+    /// Name of Spin app
+    #[clap(name = "app", long = "app")]
+    pub app: String,
+    /// Show the value even if the app manifest marks the variable as secret
+    #[clap(long = "show-secrets", takes_value = false)]
+    pub show_secrets: bool,
+    #[clap(flatten)]
+    pub manifest: ManifestArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct DiffCommand {
+    #[clap(flatten)]
+    common: CommonArgs,
+    /// Name of Spin app
+    #[clap(name = "app", long = "app")]
+    pub app: String,
+    /// Desired output format
+    #[clap(value_enum, long = "format", default_value = "plain")]
+    pub format: OutputFormat,
+    #[clap(flatten)]
+    pub manifest: ManifestArgs,
 }
 
 impl VariablesCommand {
     pub async fn run(self) -> Result<()> {
         match self {
             Self::Set(cmd) => {
+                let variables = cmd.variables().await?;
                 let (client, app_id) =
                     client_and_app_id(cmd.common.deployment_env_id.as_deref(), &cmd.app).await?;
-                set_variables(&client, app_id, &cmd.variables_to_set).await?;
+                set_variables(&client, app_id, &variables).await?;
             }
             Self::Delete(cmd) => {
                 let (client, app_id) =
@@ -73,9 +242,45 @@ impl VariablesCommand {
             Self::List(cmd) => {
                 let (client, app_id) =
                     client_and_app_id(cmd.common.deployment_env_id.as_deref(), &cmd.app).await?;
-                let var_names = get_variables(&client, app_id).await?;
-                for v in var_names {
-                    println!("{}", v.key);
+                cmd.run(&client, app_id).await?;
+            }
+            Self::Get(cmd) => {
+                let (client, app_id) =
+                    client_and_app_id(cmd.common.deployment_env_id.as_deref(), &cmd.app).await?;
+                cmd.run(&client, app_id).await?;
+            }
+            Self::Diff(cmd) => {
+                let (client, app_id) =
+                    client_and_app_id(cmd.common.deployment_env_id.as_deref(), &cmd.app).await?;
+                cmd.run(&client, app_id).await?;
+            }
+            Self::Sync(cmd) => {
+                let (client, app_id) =
+                    client_and_app_id(cmd.common.deployment_env_id.as_deref(), &cmd.app).await?;
+                cmd.run(&client, app_id).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ListCommand {
+    async fn run(&self, client: &impl CloudClientInterface, app_id: Uuid) -> Result<()> {
+        let variables = get_variables(client, app_id).await?;
+        let secrets = if self.show_values && !self.show_secrets {
+            Some(self.manifest.secret_variables().await?)
+        } else {
+            None
+        };
+        let listed = variables
+            .iter()
+            .map(|v| ListedVariable::new(v, self.show_values, secrets.as_ref()))
+            .collect::<Vec<_>>();
+        match self.format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&listed)?),
+            OutputFormat::Plain => {
+                for v in listed {
+                    println!("{v}");
                 }
             }
         }
@@ -83,40 +288,256 @@ impl VariablesCommand {
     }
 }
 
+impl GetCommand {
+    async fn run(&self, client: &impl CloudClientInterface, app_id: Uuid) -> Result<()> {
+        let variables = get_variables(client, app_id).await?;
+        let secrets = if self.show_secrets {
+            None
+        } else {
+            Some(self.manifest.secret_variables().await?)
+        };
+        println!(
+            "{}",
+            variable_value(&variables, &self.name, &self.app, secrets.as_ref())?
+        );
+        Ok(())
+    }
+}
+
+// The value of a variable as it is shown, masked if it is secret
+fn variable_value<'a>(
+    variables: &'a [Variable],
+    name: &str,
+    app: &str,
+    secrets: Option<&Secrets>,
+) -> Result<&'a str> {
+    let variable = variables
+        .iter()
+        .find(|v| v.key == name)
+        .with_context(|| format!(r#"Variable "{name}" is not set on app "{app}""#))?;
+    ListedVariable::new(variable, true, secrets)
+        .value
+        .flatten()
+        .with_context(|| format!(r#"The value of variable "{name}" is not available"#))
+}
+
+/// Which variables are secret
+enum Secrets {
+    All,
+    Only(HashSet<String>),
+}
+
+impl Secrets {
+    fn contains(&self, key: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(keys) => keys.contains(key),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ListedVariable<'a> {
+    key: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<Option<&'a str>>,
+}
+
+impl<'a> ListedVariable<'a> {
+    fn new(variable: &'a Variable, show_values: bool, secrets: Option<&Secrets>) -> Self {
+        let value = show_values.then(|| match secrets {
+            Some(secrets) if secrets.contains(&variable.key) => Some(MASKED_VALUE),
+            _ => variable.value.as_deref(),
+        });
+        Self {
+            key: &variable.key,
+            value,
+        }
+    }
+}
+
+impl std::fmt::Display for ListedVariable<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            None => write!(f, "{}", self.key),
+            Some(Some(value)) => write!(f, "{}={value}", self.key),
+            Some(None) => write!(f, "{} (value not available)", self.key),
+        }
+    }
+}
+
+impl SyncCommand {
+    async fn run(&self, client: &impl CloudClientInterface, app_id: Uuid) -> Result<()> {
+        let desired = merge_variables([read_variables_file(&self.from_file, self.format)?]);
+        let desired = SecretResolver::default().resolve_variables(desired).await?;
+        let existing = get_variables(client, app_id).await?;
+        let changes = sync::plan_sync(&desired, &existing, self.prune);
+        if changes.is_empty() {
+            println!(r#"Variables of app "{}" are up to date"#, self.app);
+            return Ok(());
+        }
+        println!(r#"Changes to the variables of app "{}":"#, self.app);
+        for change in &changes {
+            println!("  {change}");
+        }
+        if !self.yes
+            && !dialoguer::Confirm::new()
+                .with_prompt("Apply these changes?")
+                .default(false)
+                .interact_opt()?
+                .unwrap_or_default()
+        {
+            println!("No changes were applied");
+            return Ok(());
+        }
+        sync::apply_sync(client, app_id, &changes).await?;
+        println!(
+            r#"Applied {} change(s) to the variables of app "{}""#,
+            changes.len(),
+            self.app
+        );
+        Ok(())
+    }
+}
+
+impl DiffCommand {
+    async fn run(&self, client: &impl CloudClientInterface, app_id: Uuid) -> Result<()> {
+        let Some(declared) = self.manifest.variables().await? else {
+            anyhow::bail!("No application manifest found. Use --from to give the manifest.");
+        };
+        let variables = get_variables(client, app_id).await?;
+        let diff = diff_variables(&declared, &variables);
+        match self.format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+            OutputFormat::Plain => print_diff(&diff),
+        }
+        Ok(())
+    }
+}
+
+/// How a variable set in Fermyon Cloud compares to the app's declaration of it
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum VariableStatus {
+    /// Declared by the app and set
+    Set,
+    /// Declared by the app without a default, and not set
+    Missing,
+    /// Declared by the app with a default, and not set
+    Defaulted,
+    /// Set, but not declared by the app
+    Extra,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct VariableDiff {
+    key: String,
+    status: VariableStatus,
+}
+
+fn diff_variables(
+    declared: &LockedMap<ManifestVariable>,
+    variables: &[Variable],
+) -> Vec<VariableDiff> {
+    let set = variables
+        .iter()
+        .map(|v| v.key.as_str())
+        .collect::<HashSet<_>>();
+    let mut diff = declared
+        .iter()
+        .map(|(key, variable)| {
+            let status = if set.contains(key.as_str()) {
+                VariableStatus::Set
+            } else if variable.default.is_some() {
+                VariableStatus::Defaulted
+            } else {
+                VariableStatus::Missing
+            };
+            VariableDiff {
+                key: key.clone(),
+                status,
+            }
+        })
+        .chain(
+            variables
+                .iter()
+                .filter(|v| !declared.contains_key(&v.key))
+                .map(|v| VariableDiff {
+                    key: v.key.clone(),
+                    status: VariableStatus::Extra,
+                }),
+        )
+        .collect::<Vec<_>>();
+    diff.sort_by(|a, b| a.key.cmp(&b.key));
+    diff
+}
+
+fn print_diff(diff: &[VariableDiff]) {
+    let mut table = comfy_table::Table::new();
+    table.load_preset(ASCII_BORDERS_ONLY_CONDENSED);
+    table.set_header(vec!["Variable", "Status"]);
+    table.add_rows(diff.iter().map(|d| {
+        let status = match d.status {
+            VariableStatus::Set => "set",
+            VariableStatus::Missing => "MISSING: required by the app but not set",
+            VariableStatus::Defaulted => "not set: the app's default is used",
+            VariableStatus::Extra => "extra: set but not declared by the app",
+        };
+        vec![d.key.as_str(), status]
+    }));
+    println!("{table}");
+    let missing = diff
+        .iter()
+        .filter(|d| d.status == VariableStatus::Missing)
+        .count();
+    if missing > 0 {
+        println!("{missing} required variable(s) are not set. Use `spin cloud variables set` to set them.");
+    }
+}
+
 pub(crate) async fn set_variables(
-    client: &CloudClient,
+    client: &impl CloudClientInterface,
     app_id: Uuid,
     variables: &[(String, String)],
 ) -> Result<()> {
-    for var in variables {
-        CloudClient::add_variable_pair(client, app_id, var.0.to_owned(), var.1.to_owned())
+    let tasks = variables.iter().map(|var| async move {
+        client
+            .add_variable_pair(app_id, var.0.to_owned(), var.1.to_owned())
             .await
-            .with_context(|| format!("Problem creating variable {}", var.0))?;
-    }
-    Ok(())
+            .with_context(|| format!("Problem creating variable {}", var.0))
+    });
+    run_all(tasks, MAX_CONCURRENT_REQUESTS).await
 }
 
 pub(crate) async fn delete_variables(
-    client: &CloudClient,
+    client: &impl CloudClientInterface,
     app_id: Uuid,
     variables: &[String],
 ) -> Result<()> {
-    for var in variables {
-        CloudClient::delete_variable_pair(client, app_id, var.to_owned())
+    let tasks = variables.iter().map(|var| async move {
+        client
+            .delete_variable_pair(app_id, var.to_owned())
             .await
-            .with_context(|| format!("Problem deleting variable {var}"))?;
-    }
-    Ok(())
+            .with_context(|| format!("Problem deleting variable {var}"))
+    });
+    run_all(tasks, MAX_CONCURRENT_REQUESTS).await
 }
 
-async fn get_variables_json(client: &CloudClient, app_id: Uuid) -> Result<Vec<String>> {
-    let vars = CloudClient::get_variable_pairs(client, app_id)
+async fn get_variables_json(
+    client: &impl CloudClientInterface,
+    app_id: Uuid,
+) -> Result<Vec<String>> {
+    let vars = client
+        .get_variable_pairs(app_id)
         .await
         .context("Problem listing variables")?;
     Ok(vars)
 }
 
-pub(crate) async fn get_variables(client: &CloudClient, app_id: Uuid) -> Result<Vec<Variable>> {
+pub(crate) async fn get_variables(
+    client: &impl CloudClientInterface,
+    app_id: Uuid,
+) -> Result<Vec<Variable>> {
     let vars = get_variables_json(client, app_id).await?;
     let var_names = vars
         .iter()
@@ -125,3 +546,95 @@ pub(crate) async fn get_variables(client: &CloudClient, app_id: Uuid) -> Result<
         .context("could not parse variable")?;
     Ok(var_names)
 }
+
+#[cfg(test)]
+mod variables_tests {
+    use super::*;
+    use serde_json::json;
+
+    fn variable(key: &str, value: Option<&str>) -> Variable {
+        Variable {
+            key: key.to_owned(),
+            value: value.map(|v| v.to_owned()),
+        }
+    }
+
+    fn declared() -> LockedMap<ManifestVariable> {
+        serde_json::from_value(json!({
+            "api_key": { "default": null, "secret": true },
+            "greeting": { "default": "hello", "secret": false },
+            "region": { "default": null, "secret": false },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_variable_without_value_deserializes() {
+        let v: Variable = from_str(r#"{"key": "greeting"}"#).unwrap();
+        assert_eq!("greeting", v.key);
+        assert_eq!(None, v.value);
+    }
+
+    #[test]
+    fn test_secret_values_are_masked() {
+        let secrets = Secrets::Only(HashSet::from(["api_key".to_owned()]));
+        let api_key = variable("api_key", Some("s3cr3t"));
+        let region = variable("region", Some("eu"));
+
+        let listed = ListedVariable::new(&api_key, true, Some(&secrets));
+        assert_eq!("api_key=********", listed.to_string());
+        let listed = ListedVariable::new(&region, true, Some(&secrets));
+        assert_eq!("region=eu", listed.to_string());
+        let listed = ListedVariable::new(&region, true, Some(&Secrets::All));
+        assert_eq!("region=********", listed.to_string());
+        let listed = ListedVariable::new(&api_key, true, None);
+        assert_eq!("api_key=s3cr3t", listed.to_string());
+        let listed = ListedVariable::new(&api_key, false, Some(&secrets));
+        assert_eq!("api_key", listed.to_string());
+        assert_eq!(
+            json!({"key": "api_key"}),
+            serde_json::to_value(&listed).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_masks_secret_values() {
+        let secrets = Secrets::Only(HashSet::from(["api_key".to_owned()]));
+        let variables = vec![
+            variable("api_key", Some("s3cr3t")),
+            variable("region", Some("eu")),
+            variable("greeting", None),
+        ];
+
+        let value = |name| variable_value(&variables, name, "app1", Some(&secrets));
+        assert_eq!("********", value("api_key").unwrap());
+        assert_eq!("eu", value("region").unwrap());
+        assert_eq!(
+            "s3cr3t",
+            variable_value(&variables, "api_key", "app1", None).unwrap()
+        );
+        assert_eq!(
+            r#"Variable "missing" is not set on app "app1""#,
+            value("missing").unwrap_err().to_string()
+        );
+        value("greeting").expect_err("should have had no value to show");
+    }
+
+    #[test]
+    fn test_diff_reports_missing_extra_and_defaulted_variables() {
+        let set = vec![variable("api_key", None), variable("old_setting", None)];
+        let diff = diff_variables(&declared(), &set)
+            .into_iter()
+            .map(|d| (d.key, d.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("api_key".to_owned(), VariableStatus::Set),
+                ("greeting".to_owned(), VariableStatus::Defaulted),
+                ("old_setting".to_owned(), VariableStatus::Extra),
+                ("region".to_owned(), VariableStatus::Missing),
+            ],
+            diff
+        );
+    }
+}
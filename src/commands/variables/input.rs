@@ -0,0 +1,171 @@
+//! Reading variables from files and stdin, so that values do not have to be
+//! given on the command line where they end up in shell history and logs
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+
+/// The file next to an app's manifest that deploy reads variables from
+pub(crate) const CLOUD_ENV_FILE: &str = ".env.cloud";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum VariablesFormat {
+    /// NAME=value lines, as in a .env file
+    Env,
+    /// A JSON object of names to values
+    Json,
+    /// A TOML table of names to values
+    Toml,
+}
+
+impl VariablesFormat {
+    /// Guesses the format of a file from its extension, defaulting to .env
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("json") => Self::Json,
+            Some(e) if e.eq_ignore_ascii_case("toml") => Self::Toml,
+            _ => Self::Env,
+        }
+    }
+}
+
+/// Reads variables from a file, in the given format or else the one its name
+/// suggests
+pub(crate) fn read_variables_file(
+    path: &Path,
+    format: Option<VariablesFormat>,
+) -> Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read variables file '{}'", path.display()))?;
+    let format = format.unwrap_or_else(|| VariablesFormat::from_path(path));
+    parse_variables(&content, format)
+        .with_context(|| format!("Could not parse variables file '{}'", path.display()))
+}
+
+/// Reads variables from stdin, in the .env format unless another is given
+pub(crate) fn read_variables_stdin(
+    format: Option<VariablesFormat>,
+) -> Result<Vec<(String, String)>> {
+    let mut content = String::new();
+    std::io::stdin()
+        .read_to_string(&mut content)
+        .context("Could not read variables from stdin")?;
+    parse_variables(&content, format.unwrap_or(VariablesFormat::Env))
+        .context("Could not parse variables from stdin")
+}
+
+fn parse_variables(content: &str, format: VariablesFormat) -> Result<Vec<(String, String)>> {
+    match format {
+        VariablesFormat::Env => dotenvy::from_read_iter(content.as_bytes())
+            .map(|pair| pair.context("Invalid .env file"))
+            .collect(),
+        VariablesFormat::Json => {
+            let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(content)
+                .context("Expected a JSON object of names to values")?;
+            object
+                .into_iter()
+                .map(|(name, value)| match value {
+                    serde_json::Value::String(value) => Ok((name, value)),
+                    serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                        Ok((name, value.to_string()))
+                    }
+                    _ => bail!("The value of variable '{name}' is not a string, number or boolean"),
+                })
+                .collect()
+        }
+        VariablesFormat::Toml => {
+            let table: toml::Table =
+                toml::from_str(content).context("Expected a TOML table of names to values")?;
+            table
+                .into_iter()
+                .map(|(name, value)| match value {
+                    toml::Value::String(value) => Ok((name, value)),
+                    toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                        Ok((name, value.to_string()))
+                    }
+                    _ => bail!("The value of variable '{name}' is not a string, number or boolean"),
+                })
+                .collect()
+        }
+    }
+}
+
+/// Combines lists of variables, later lists taking precedence over earlier
+/// ones. Each variable appears once, in the position it was first given in.
+pub(crate) fn merge_variables(
+    lists: impl IntoIterator<Item = Vec<(String, String)>>,
+) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = vec![];
+    for (name, value) in lists.into_iter().flatten() {
+        match merged.iter_mut().find(|(n, _)| *n == name) {
+            Some(existing) => existing.1 = value,
+            None => merged.push((name, value)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod input_tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_variables_are_parsed_from_each_format() {
+        let expected = pairs(&[("api_key", "s3cr3t"), ("retries", "3")]);
+        assert_eq!(
+            expected,
+            parse_variables(
+                "# comment\napi_key=\"s3cr3t\"\nretries=3\n",
+                VariablesFormat::Env
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            expected,
+            parse_variables(
+                r#"{"api_key": "s3cr3t", "retries": 3}"#,
+                VariablesFormat::Json
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            expected,
+            parse_variables("api_key = \"s3cr3t\"\nretries = 3\n", VariablesFormat::Toml).unwrap()
+        );
+        parse_variables("[nested]\nkey = 1\n", VariablesFormat::Toml)
+            .expect_err("should not have accepted a nested table");
+    }
+
+    #[test]
+    fn test_format_is_guessed_from_file_name() {
+        assert_eq!(
+            VariablesFormat::Json,
+            VariablesFormat::from_path(Path::new("vars.JSON"))
+        );
+        assert_eq!(
+            VariablesFormat::Toml,
+            VariablesFormat::from_path(Path::new("vars.toml"))
+        );
+        assert_eq!(
+            VariablesFormat::Env,
+            VariablesFormat::from_path(Path::new(".env.cloud"))
+        );
+    }
+
+    #[test]
+    fn test_later_variables_take_precedence() {
+        let merged = merge_variables([
+            pairs(&[("a", "1"), ("b", "1")]),
+            pairs(&[("b", "2"), ("c", "2")]),
+        ]);
+        assert_eq!(pairs(&[("a", "1"), ("b", "2"), ("c", "2")]), merged);
+    }
+}
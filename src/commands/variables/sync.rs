@@ -0,0 +1,178 @@
+//! Bringing the variables set on an app in line with a file of variables
+use anyhow::Result;
+use cloud::CloudClientInterface;
+use uuid::Uuid;
+
+use super::{delete_variables, set_variables, Variable};
+use crate::commands::parallel::combine_errors;
+
+/// A change needed to make the variables of an app match the file
+#[derive(Debug, PartialEq)]
+pub(super) enum VariableChange {
+    Add { name: String, value: String },
+    Update { name: String, value: String },
+    Delete { name: String },
+}
+
+impl std::fmt::Display for VariableChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Values are deliberately not printed as they may be secrets
+        match self {
+            Self::Add { name, .. } => write!(f, r#"+ add variable "{name}""#),
+            Self::Update { name, .. } => write!(f, r#"~ update variable "{name}""#),
+            Self::Delete { name } => write!(f, r#"- delete variable "{name}""#),
+        }
+    }
+}
+
+/// Works out the changes that make the variables set match the desired ones.
+/// Variables whose current value is known to be the desired one are left
+/// alone. Variables that are not desired are only deleted if `prune` is set.
+pub(super) fn plan_sync(
+    desired: &[(String, String)],
+    existing: &[Variable],
+    prune: bool,
+) -> Vec<VariableChange> {
+    let mut changes = vec![];
+    for (name, value) in desired {
+        match existing.iter().find(|v| &v.key == name) {
+            None => changes.push(VariableChange::Add {
+                name: name.clone(),
+                value: value.clone(),
+            }),
+            Some(v) if v.value.as_ref() == Some(value) => {}
+            Some(_) => changes.push(VariableChange::Update {
+                name: name.clone(),
+                value: value.clone(),
+            }),
+        }
+    }
+    if prune {
+        changes.extend(
+            existing
+                .iter()
+                .filter(|v| !desired.iter().any(|(name, _)| name == &v.key))
+                .map(|v| VariableChange::Delete {
+                    name: v.key.clone(),
+                }),
+        );
+    }
+    changes
+}
+
+pub(super) async fn apply_sync(
+    client: &impl CloudClientInterface,
+    app_id: Uuid,
+    changes: &[VariableChange],
+) -> Result<()> {
+    let to_set = changes
+        .iter()
+        .filter_map(|c| match c {
+            VariableChange::Add { name, value } | VariableChange::Update { name, value } => {
+                Some((name.clone(), value.clone()))
+            }
+            VariableChange::Delete { .. } => None,
+        })
+        .collect::<Vec<_>>();
+    let to_delete = changes
+        .iter()
+        .filter_map(|c| match c {
+            VariableChange::Delete { name } => Some(name.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let (set, deleted) = futures::join!(
+        set_variables(client, app_id, &to_set),
+        delete_variables(client, app_id, &to_delete)
+    );
+    combine_errors([set.err(), deleted.err()].into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod sync_tests {
+    use super::*;
+    use cloud::MockCloudClientInterface;
+
+    fn existing() -> Vec<Variable> {
+        vec![
+            Variable {
+                key: "same".to_owned(),
+                value: Some("1".to_owned()),
+            },
+            Variable {
+                key: "changed".to_owned(),
+                value: Some("1".to_owned()),
+            },
+            Variable {
+                key: "unknown".to_owned(),
+                value: None,
+            },
+            Variable {
+                key: "stale".to_owned(),
+                value: None,
+            },
+        ]
+    }
+
+    fn desired() -> Vec<(String, String)> {
+        ["same", "changed", "unknown", "new"]
+            .into_iter()
+            .map(|name| {
+                (
+                    name.to_owned(),
+                    if name == "same" { "1" } else { "2" }.to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sync_adds_updates_and_prunes() {
+        let changes = plan_sync(&desired(), &existing(), true)
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                r#"~ update variable "changed""#,
+                r#"~ update variable "unknown""#,
+                r#"+ add variable "new""#,
+                r#"- delete variable "stale""#,
+            ],
+            changes
+        );
+    }
+
+    #[test]
+    fn test_sync_keeps_stale_variables_without_prune() {
+        let changes = plan_sync(&desired(), &existing(), false);
+        assert!(!changes
+            .iter()
+            .any(|c| matches!(c, VariableChange::Delete { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_apply_sync_sets_and_deletes_variables() -> Result<()> {
+        let app_id = Uuid::new_v4();
+        let mut mock = MockCloudClientInterface::new();
+        mock.expect_add_variable_pair()
+            .withf(move |id, name, value| *id == app_id && name == "new" && value == "2")
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        mock.expect_delete_variable_pair()
+            .withf(move |id, name| *id == app_id && name == "stale")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let changes = vec![
+            VariableChange::Add {
+                name: "new".to_owned(),
+                value: "2".to_owned(),
+            },
+            VariableChange::Delete {
+                name: "stale".to_owned(),
+            },
+        ];
+        apply_sync(&mock, app_id, &changes).await
+    }
+}
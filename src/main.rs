@@ -6,6 +6,7 @@ mod spin;
 use anyhow::{Error, Result};
 use clap::{FromArgMatches, Parser};
 use commands::{
+    apply::ApplyCommand,
     apps::AppsCommand,
     deploy::DeployCommand,
     key_value::KeyValueCommand,
@@ -35,6 +36,8 @@ enum CloudCli {
     Apps(AppsCommand),
     /// Package and upload an application to the Fermyon Cloud.
     Deploy(DeployCommand),
+    /// Converge an app's cloud resources to match a cloud manifest
+    Apply(ApplyCommand),
     /// Log into Fermyon Cloud
     Login(LoginCommand),
     /// Log out of Fermyon Cloud
@@ -70,6 +73,7 @@ async fn main() -> Result<(), Error> {
     match cli {
         CloudCli::Apps(cmd) => cmd.run().await,
         CloudCli::Deploy(cmd) => cmd.run().await,
+        CloudCli::Apply(cmd) => cmd.run().await,
         CloudCli::Login(cmd) => cmd.run().await,
         CloudCli::Logout(cmd) => cmd.run().await,
         CloudCli::Logs(cmd) => cmd.run().await,